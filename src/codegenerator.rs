@@ -1,135 +1,948 @@
-use crate::node::{Node, NodeKind};
-use crate::lexer::Input;
+use std::fmt;
+use crate::error::CompileError;
+use crate::node::{
+    BinOp, Data, Expr, ExprKind, Function, GlobalDecl, Program, Stmt, StmtKind, Symbol, UnaryOp, Var,
+};
+use crate::types::Type;
 
-pub struct CodeGenerator {
-    head: Option<Node>,
+// registers for function arguments, see System V ABI
+const ARG_REGS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+// lower 8, 16 and 32 bits of `ARG_REGS`
+const ARG_REGS8: [&str; 6] = ["dil", "sil", "dl", "cl", "r8b", "r9b"];
+const ARG_REGS16: [&str; 6] = ["di", "si", "dx", "cx", "r8w", "r9w"];
+const ARG_REGS32: [&str; 6] = ["edi", "esi", "edx", "ecx", "r8d", "r9d"];
+
+// Append a line to the output assembly, like `println!`.
+// The first write error is kept and reported at the end.
+macro_rules! emit {
+    ($gen:expr, $($arg:tt)*) => {{
+        let result = writeln!($gen.output, $($arg)*);
+        $gen.write_result = $gen.write_result.and(result);
+    }};
+}
+
+// CodeGenerator writes the assembly of a checked program to any `fmt::Write` sink.
+pub struct CodeGenerator<W> {
+    output: W,
+    write_result: fmt::Result,
+    // counter to make unique labels
+    label_count: usize,
+    // name of the function being generated
+    current_fn: String,
+    // string literals, labeled `.L.str.<index>`
+    strings: Vec<Vec<u8>>,
+    // number of 8-byte values pushed in the current function, to align rsp for calls
+    depth: usize,
+    // offset of the register save area and the bytes of the named parameters in it,
+    // if the current function is variadic
+    va_area: Option<(usize, usize)>,
 }
 
 
-impl CodeGenerator {
-    pub fn new() -> Self {
+impl<W: fmt::Write> CodeGenerator<W> {
+    pub fn new(output: W) -> Self {
         Self {
-            head: None,
+            output,
+            write_result: Ok(()),
+            label_count: 0,
+            current_fn: String::new(),
+            strings: Vec::new(),
+            depth: 0,
+            va_area: None,
         }
     }
 
+    // Write x86-64 assembly (intel syntax) of `program`, which must have been checked by `Sema`.
+    pub fn generate(&mut self, program: &Program) -> Result<(), CompileError> {
+        emit!(self, ".intel_syntax noprefix");
+        for global in &program.globals {
+            self.gen_global(global);
+        }
+        emit!(self, ".text");
+        for function in &program.functions {
+            self.gen_function(function)?;
+        }
+        self.gen_strings();
+        self.write_result.map_err(|_| CompileError::new("cannot write the assembly"))
+    }
 
-    pub fn from_node(head: Node) -> Self {
-        Self {
-            head: Some(head),
+    // return the output sink
+    pub fn into_output(self) -> W {
+        self.output
+    }
+
+    // Global variables with initial data go to `.data`, the others to `.bss`.
+    fn gen_global(&mut self, global: &GlobalDecl) {
+        let var = &global.var;
+        match &global.data {
+            Some(_) => emit!(self, ".data"),
+            None => emit!(self, ".bss"),
+        }
+        emit!(self, ".global {}", var.name);
+        emit!(self, ".align {}", var.ty.align());
+        emit!(self, "{}:", var.name);
+        let data = match &global.data {
+            Some(data) => data,
+            None => {
+                emit!(self, "    .zero {}", var.ty.size());
+                return;
+            },
+        };
+        for item in data {
+            match item {
+                Data::Bytes(bytes) if bytes.iter().all(|b| *b == 0) => {
+                    emit!(self, "    .zero {}", bytes.len());
+                },
+                Data::Bytes(bytes) => {
+                    let bytes: Vec<String> = bytes.iter().map(|b| b.to_string()).collect();
+                    emit!(self, "    .byte {}", bytes.join(", "));
+                },
+                Data::Addr(symbol, offset) => {
+                    let label = match symbol {
+                        Symbol::Global(name) => name.clone(),
+                        Symbol::Str(bytes) => self.string_label(bytes),
+                    };
+                    match offset {
+                        0 => emit!(self, "    .quad {}", label),
+                        _ => emit!(self, "    .quad {}{:+}", label, offset),
+                    }
+                },
+            }
         }
     }
-        
-    pub fn from_str(s: &str) -> Self {
-        let mut input = Input::new(s);
-        let mut nodes = input.tokenize();
-        // TODO
-        let head = nodes.pop().unwrap();
-        Self {
-            head: Some(head),
+
+    // label of a new string literal, emitted by `gen_strings`
+    fn string_label(&mut self, bytes: &[u8]) -> String {
+        self.strings.push(bytes.to_vec());
+        format!(".L.str.{}", self.strings.len() - 1)
+    }
+
+    // read-only data of the string literals, with the terminating NULs
+    fn gen_strings(&mut self) {
+        if self.strings.is_empty() {
+            return;
+        }
+        emit!(self, ".section .rodata");
+        for (i, bytes) in std::mem::take(&mut self.strings).iter().enumerate() {
+            let bytes: Vec<String> = bytes.iter().chain(&[0]).map(|b| b.to_string()).collect();
+            emit!(self, ".L.str.{}:", i);
+            emit!(self, "    .byte {}", bytes.join(", "));
         }
     }
 
-    pub fn compile(&mut self) {
-        println!(".intel_syntax noprefix");
-        println!(".global main");
-        println!("main:");
-        if let Some(head) = Option::take(&mut self.head) {
-            Self::gen(&head);
-            println!("    pop rax");
+    fn gen_function(&mut self, function: &Function) -> Result<(), CompileError> {
+        self.current_fn = function.name.clone();
+        emit!(self, ".global {}", function.name);
+        emit!(self, "{}:", function.name);
+
+        // prologue: reserve the stack frame for local variables
+        emit!(self, "    push rbp");
+        emit!(self, "    mov rbp, rsp");
+        emit!(self, "    sub rsp, {}", function.stack_size());
+
+        // save arguments passed in registers to the stack
+        for (i, param) in function.params.iter().enumerate() {
+            let reg = match param.var.ty.size() {
+                1 => ARG_REGS8[i],
+                2 => ARG_REGS16[i],
+                4 => ARG_REGS32[i],
+                _ => ARG_REGS[i],
+            };
+            emit!(self, "    mov [rbp-{}], {}", param.var.offset, reg);
+        }
+
+        // Save all the argument registers for `va_arg`,
+        // the general-purpose ones at 0..48 and the XMM ones at 48..176 of the register save area.
+        if let Some(area) = function.va_area {
+            for (i, reg) in ARG_REGS.iter().enumerate() {
+                emit!(self, "    mov [rbp-{}], {}", area - i * 8, reg);
+            }
+            for i in 0..8 {
+                emit!(self, "    movsd [rbp-{}], xmm{}", area - ARG_REGS.len() * 8 - i * 16, i);
+            }
+        }
+        self.va_area = function.va_area.map(|area| (area, function.params.len() * 8));
+
+        for stmt in &function.body {
+            self.gen_stmt(stmt)?;
         }
-        println!("    ret");
+        debug_assert_eq!(self.depth, 0);
+
+        // epilogue: the return value is in rax.
+        // Without `return`, it is the value of the last statement.
+        emit!(self, ".L.return.{}:", function.name);
+        emit!(self, "    mov rsp, rbp");
+        emit!(self, "    pop rbp");
+        emit!(self, "    ret");
+        Ok(())
     }
 
-    fn gen(node: &Node) {
+    fn push(&mut self, operand: impl fmt::Display) {
+        emit!(self, "    push {}", operand);
+        self.depth += 1;
+    }
+
+    fn pop(&mut self, reg: &str) {
+        emit!(self, "    pop {}", reg);
+        self.depth -= 1;
+    }
+
+    fn new_label(&mut self) -> usize {
+        self.label_count += 1;
+        self.label_count
+    }
 
-        if let Some(child) = node.lhs() {
-            Self::gen(child);
+    fn gen_stmt(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                self.gen(expr)?;
+                // discard the value of the expression statement, keeping it in rax
+                self.pop("rax");
+            },
+            StmtKind::Return(expr) => {
+                self.gen(expr)?;
+                self.pop("rax");
+                emit!(self, "    jmp .L.return.{}", self.current_fn);
+            },
+            StmtKind::If {cond, then, els} => {
+                let label = self.new_label();
+                self.gen(cond)?;
+                self.pop("rax");
+                emit!(self, "    cmp rax, 0");
+                emit!(self, "    je .L.else.{}", label);
+                self.gen_stmt(then)?;
+                emit!(self, "    jmp .L.end.{}", label);
+                emit!(self, ".L.else.{}:", label);
+                if let Some(els) = els {
+                    self.gen_stmt(els)?;
+                }
+                emit!(self, ".L.end.{}:", label);
+            },
+            StmtKind::While {cond, body} => {
+                let label = self.new_label();
+                emit!(self, ".L.begin.{}:", label);
+                self.gen(cond)?;
+                self.pop("rax");
+                emit!(self, "    cmp rax, 0");
+                emit!(self, "    je .L.end.{}", label);
+                self.gen_stmt(body)?;
+                emit!(self, "    jmp .L.begin.{}", label);
+                emit!(self, ".L.end.{}:", label);
+            },
+            StmtKind::Block(body) => {
+                for stmt in body {
+                    self.gen_stmt(stmt)?;
+                }
+            },
+            StmtKind::For {init, cond, inc, body} => {
+                let label = self.new_label();
+                if let Some(init) = init {
+                    self.gen(init)?;
+                    self.pop("rax");
+                }
+                emit!(self, ".L.begin.{}:", label);
+                if let Some(cond) = cond {
+                    self.gen(cond)?;
+                    self.pop("rax");
+                    emit!(self, "    cmp rax, 0");
+                    emit!(self, "    je .L.end.{}", label);
+                }
+                self.gen_stmt(body)?;
+                if let Some(inc) = inc {
+                    self.gen(inc)?;
+                    self.pop("rax");
+                }
+                emit!(self, "    jmp .L.begin.{}", label);
+                emit!(self, ".L.end.{}:", label);
+            },
+            StmtKind::Decl(decls) => {
+                for decl in decls {
+                    if let Some(init) = &decl.init {
+                        emit!(self, "    lea rax, [rbp-{}]", decl.var.offset);
+                        self.push("rax");
+                        self.gen(init)?;
+                        self.store(&decl.var.ty);
+                        self.pop("rax");
+                    }
+                }
+            },
         }
-        if let Some(child) = node.rhs() {
-            Self::gen(child);
+        Ok(())
+    }
+
+    // Push the return value of `name(args...)`, which is of type `ret_ty`.
+    // Arguments after the sixth are passed on the stack, the seventh at the lowest address.
+    fn gen_call(&mut self, name: &str, args: &[Expr], ret_ty: &Type) -> Result<(), CompileError> {
+        // rsp must be aligned to 16 bytes at `call`, with the stack arguments pushed
+        let stack_args = args.len().saturating_sub(ARG_REGS.len());
+        let padding = (self.depth + stack_args) % 2;
+        if padding == 1 {
+            emit!(self, "    sub rsp, 8");
+            self.depth += 1;
         }
-        
-        match node.kind() {
-            NodeKind::Num(n) => {
-                println!("    push {}", n);
-                return;
+        // the first six arguments are popped to the registers from the stack top
+        for arg in args.iter().rev() {
+            self.gen(arg)?;
+        }
+        for reg in ARG_REGS.iter().take(args.len()) {
+            self.pop(reg);
+        }
+
+        // al is the number of vector registers used by the arguments, for variadic functions
+        emit!(self, "    mov eax, 0");
+        emit!(self, "    call {}", name);
+        let size = stack_args + padding;
+        if size > 0 {
+            emit!(self, "    add rsp, {}", size * 8);
+            self.depth -= size;
+        }
+        // the upper bits of a return value smaller than 64 bits are undefined
+        self.cast(ret_ty);
+        self.push("rax");
+        Ok(())
+    }
+
+    // push the address of the lvalue `expr`
+    fn gen_lval(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        match &expr.kind {
+            ExprKind::Var {var: Some(Var::Local(var)), ..} => {
+                emit!(self, "    lea rax, [rbp-{}]", var.offset);
+                self.push("rax");
+                Ok(())
             },
-            NodeKind::Op(op) => {
-                println!("    pop rdi");
-                println!("    pop rax");
-                match op as &str {
-                    "+" => {
-                        println!("    add rax, rdi");
-                    },
-                    "-" => {
-                        println!("    sub rax, rdi");
-                    },
-                    "*" => {
-                        println!("    imul rax, rdi");
-                    },
-                    "/" => {
-                        println!("    cqo");
-                        println!("    idiv rdi");
-                    },
-                    "==" => {
-                        // if rax == rdi, then set 1 to flag register
-                        println!("    cmp rax, rdi");
-                        // load the value of the flag register to al, which is lower 8 bits of rax
-                        println!("    sete al");
-                        // clear upper 56 bits with 0s
-                        println!("    movzb rax, al");
-                    },
-                    "!=" => {
-                        println!("    cmp rax, rdi");
-                        println!("    setne al");
-                        println!("    movzb rax, al");
-                    },
-                    "<" => {
-                        println!("    cmp rax, rdi");
-                        println!("    setl al");
-                        println!("    movzb rax, al");
-                    },
-                    "<=" => {
-                        println!("    cmp rax, rdi");
-                        println!("    setle al");
-                        println!("    movzb rax, al");
-                    },
-                    "=" => {
+            ExprKind::Var {var: Some(Var::Global(var)), ..} => {
+                emit!(self, "    lea rax, [rip+{}]", var.name);
+                self.push("rax");
+                Ok(())
+            },
+            ExprKind::Str(bytes) => {
+                let label = self.string_label(bytes);
+                emit!(self, "    lea rax, [rip+{}]", label);
+                self.push("rax");
+                Ok(())
+            },
+            // the address is the value of the operand
+            ExprKind::Unary(UnaryOp::Deref, operand) => self.gen(operand),
+            // the value of a struct is its address
+            ExprKind::Member {base, member: Some(member), ..} => {
+                self.gen(base)?;
+                self.pop("rax");
+                emit!(self, "    add rax, {}", member.offset);
+                self.push("rax");
+                Ok(())
+            },
+            _ => Err(CompileError::new("not an lvalue")),
+        }
+    }
 
+    // push the value of `expr`
+    fn gen(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        match &expr.kind {
+            // `push` takes a 32-bit immediate
+            ExprKind::Num(n) if i32::MIN as i64 <= *n && *n <= i32::MAX as i64 => {
+                self.push(n);
+            },
+            ExprKind::Num(n) => {
+                emit!(self, "    movabs rax, {}", n);
+                self.push("rax");
+            },
+            ExprKind::Var {..} | ExprKind::Str(_) | ExprKind::Unary(UnaryOp::Deref, _) | ExprKind::Member {..} => {
+                self.gen_lval(expr)?;
+                self.load(expr.ty());
+            },
+            ExprKind::Unary(UnaryOp::Addr, operand) => {
+                self.gen_lval(operand)?;
+            },
+            ExprKind::Assign(lhs, rhs) => {
+                self.gen_lval(lhs)?;
+                self.gen(rhs)?;
+                self.store(expr.ty());
+            },
+            ExprKind::FuncCall {name, args} => {
+                self.gen_call(name, args, expr.ty())?;
+            },
+            // initialize the `va_list`, whose struct is at the address of `ap`
+            ExprKind::VaStart(ap) => {
+                let (area, gp_offset) = self.va_area.expect("`va_start` in a variadic function");
+                self.gen(ap)?;
+                self.pop("rax");
+                emit!(self, "    mov dword ptr [rax], {}", gp_offset);
+                emit!(self, "    mov dword ptr [rax+4], {}", ARG_REGS.len() * 8);
+                // arguments after the sixth are above the return address
+                emit!(self, "    lea rdi, [rbp+16]");
+                emit!(self, "    mov [rax+8], rdi");
+                emit!(self, "    lea rdi, [rbp-{}]", area);
+                emit!(self, "    mov [rax+16], rdi");
+                self.push(0);
+            },
+            // The next argument is in the register save area until `gp_offset` reaches its end,
+            // and then in the overflow area on the stack.
+            ExprKind::VaArg(ap, ty) => {
+                let label = self.new_label();
+                self.gen(ap)?;
+                self.pop("rdi");
+                emit!(self, "    mov eax, [rdi]");
+                emit!(self, "    cmp eax, {}", ARG_REGS.len() * 8);
+                emit!(self, "    jae .L.overflow.{}", label);
+                emit!(self, "    add rax, [rdi+16]");
+                emit!(self, "    add dword ptr [rdi], 8");
+                emit!(self, "    jmp .L.end.{}", label);
+                emit!(self, ".L.overflow.{}:", label);
+                emit!(self, "    mov rax, [rdi+8]");
+                emit!(self, "    lea rdx, [rax+8]");
+                emit!(self, "    mov [rdi+8], rdx");
+                emit!(self, ".L.end.{}:", label);
+                self.push("rax");
+                self.load(ty);
+            },
+            ExprKind::Sizeof(_) => unreachable!("`sizeof` is evaluated by Sema"),
+            ExprKind::AssignOp(..) | ExprKind::PostIncDec(..) => unreachable!("rewritten by Sema"),
+            ExprKind::Binary(op @ (BinOp::LogAnd | BinOp::LogOr), lhs, rhs) => {
+                // evaluate `rhs` only if `lhs` does not decide the result
+                let label = self.new_label();
+                let (short, jump) = if *op == BinOp::LogAnd { ("false", "je") } else { ("true", "jne") };
+                for operand in [lhs, rhs].iter() {
+                    self.gen(operand)?;
+                    self.pop("rax");
+                    emit!(self, "    cmp rax, 0");
+                    emit!(self, "    {} .L.{}.{}", jump, short, label);
+                }
+                self.push((*op == BinOp::LogAnd) as i32);
+                emit!(self, "    jmp .L.end.{}", label);
+                // only one of the values is pushed
+                self.depth -= 1;
+                emit!(self, ".L.{}.{}:", short, label);
+                self.push((*op == BinOp::LogOr) as i32);
+                emit!(self, ".L.end.{}:", label);
+            },
+            ExprKind::Cond {cond, then, els} => {
+                let label = self.new_label();
+                self.gen(cond)?;
+                self.pop("rax");
+                emit!(self, "    cmp rax, 0");
+                emit!(self, "    je .L.else.{}", label);
+                self.gen(then)?;
+                emit!(self, "    jmp .L.end.{}", label);
+                // only one of the branches is executed
+                self.depth -= 1;
+                emit!(self, ".L.else.{}:", label);
+                self.gen(els)?;
+                emit!(self, ".L.end.{}:", label);
+            },
+            ExprKind::Comma(lhs, rhs) => {
+                self.gen(lhs)?;
+                self.pop("rax");
+                self.gen(rhs)?;
+            },
+            ExprKind::Cast(operand) => {
+                self.gen(operand)?;
+                // conversions to pointers do not change the value
+                if expr.ty().is_integer() {
+                    self.pop("rax");
+                    self.cast(expr.ty());
+                    self.push("rax");
+                }
+            },
+            ExprKind::Unary(op, operand) => {
+                self.gen(operand)?;
+                self.pop("rax");
+                match op {
+                    UnaryOp::Neg => emit!(self, "    neg rax"),
+                    UnaryOp::Not => {
+                        emit!(self, "    cmp rax, 0");
+                        emit!(self, "    sete al");
+                        emit!(self, "    movzb rax, al");
                     },
-                    _ => {
-                        panic!("compile error");
-                    }
+                    UnaryOp::BitNot => emit!(self, "    not rax"),
+                    UnaryOp::Addr | UnaryOp::Deref => unreachable!(),
                 }
-                println!("    push rax");
-                return;
+                self.cast(expr.ty());
+                self.push("rax");
             },
-            NodeKind::LVar(ident) => {
-                
+            ExprKind::Binary(op, lhs, rhs) => {
+                self.gen(lhs)?;
+                self.gen(rhs)?;
+                self.pop("rdi");
+                self.pop("rax");
+                self.gen_binary(*op, lhs.ty());
+                self.cast(expr.ty());
+                self.push("rax");
+            },
+        }
+        Ok(())
+    }
+
+    // replace the address on the stack top with the value of type `ty` there
+    fn load(&mut self, ty: &Type) {
+        // The value of an array is the address of its first element.
+        // Structs are also handled by their addresses.
+        if ty.is_array() || ty.is_struct() {
+            return;
+        }
+        self.pop("rax");
+        // extend the value to 64 bits by the signedness
+        match (ty.size(), ty.is_unsigned()) {
+            (1, false) => emit!(self, "    movsx rax, byte ptr [rax]"),
+            (1, true) => emit!(self, "    movzx eax, byte ptr [rax]"),
+            (2, false) => emit!(self, "    movsx rax, word ptr [rax]"),
+            (2, true) => emit!(self, "    movzx eax, word ptr [rax]"),
+            (4, false) => emit!(self, "    movsxd rax, dword ptr [rax]"),
+            // writing to a 32-bit register clears the upper 32 bits
+            (4, true) => emit!(self, "    mov eax, [rax]"),
+            _ => emit!(self, "    mov rax, [rax]"),
+        }
+        self.push("rax");
+    }
+
+    // Store the value on the stack top to the address below it.
+    // They are replaced with the value, which is the value of an assignment.
+    fn store(&mut self, ty: &Type) {
+        self.pop("rdi");
+        self.pop("rax");
+        // copy a struct byte by byte from the address in rdi
+        if ty.is_struct() {
+            for i in 0..ty.size() {
+                emit!(self, "    mov r8b, [rdi+{}]", i);
+                emit!(self, "    mov [rax+{}], r8b", i);
             }
+            self.push("rdi");
+            return;
         }
+        match ty.size() {
+            1 => emit!(self, "    mov [rax], dil"),
+            2 => emit!(self, "    mov [rax], di"),
+            4 => emit!(self, "    mov [rax], edi"),
+            _ => emit!(self, "    mov [rax], rdi"),
+        }
+        self.push("rdi");
     }
 
-}
+    // Convert the value in rax to `ty`.
+    // Values of integer types are always kept extended to 64 bits,
+    // so conversions truncate the value and extend it again.
+    fn cast(&mut self, ty: &Type) {
+        if !ty.is_integer() {
+            return;
+        }
+        match (ty.size(), ty.is_unsigned()) {
+            (1, false) => emit!(self, "    movsx rax, al"),
+            (1, true) => emit!(self, "    movzx eax, al"),
+            (2, false) => emit!(self, "    movsx rax, ax"),
+            (2, true) => emit!(self, "    movzx eax, ax"),
+            (4, false) => emit!(self, "    movsxd rax, eax"),
+            (4, true) => emit!(self, "    mov eax, eax"),
+            _ => {},
+        }
+    }
+
+    // rax = rax op rdi, where the operands are of type `ty`
+    fn gen_binary(&mut self, op: BinOp, ty: &Type) {
+        // addresses are compared as unsigned
+        let unsigned = ty.is_unsigned() || ty.base().is_some();
+        match op {
+            BinOp::Add => {
+                emit!(self, "    add rax, rdi");
+            },
+            BinOp::Sub => {
+                emit!(self, "    sub rax, rdi");
+            },
+            BinOp::Mul => {
+                emit!(self, "    imul rax, rdi");
+            },
+            BinOp::Div if unsigned => {
+                emit!(self, "    mov edx, 0");
+                emit!(self, "    div rdi");
+            },
+            BinOp::Div => {
+                emit!(self, "    cqo");
+                emit!(self, "    idiv rdi");
+            },
+            // the remainder is left in rdx
+            BinOp::Mod if unsigned => {
+                emit!(self, "    mov edx, 0");
+                emit!(self, "    div rdi");
+                emit!(self, "    mov rax, rdx");
+            },
+            BinOp::Mod => {
+                emit!(self, "    cqo");
+                emit!(self, "    idiv rdi");
+                emit!(self, "    mov rax, rdx");
+            },
+            BinOp::BitAnd => {
+                emit!(self, "    and rax, rdi");
+            },
+            BinOp::BitOr => {
+                emit!(self, "    or rax, rdi");
+            },
+            BinOp::BitXor => {
+                emit!(self, "    xor rax, rdi");
+            },
+            // the shift count must be in cl
+            BinOp::Shl => {
+                emit!(self, "    mov rcx, rdi");
+                emit!(self, "    shl rax, cl");
+            },
+            BinOp::Shr if unsigned => {
+                emit!(self, "    mov rcx, rdi");
+                emit!(self, "    shr rax, cl");
+            },
+            BinOp::Shr => {
+                emit!(self, "    mov rcx, rdi");
+                emit!(self, "    sar rax, cl");
+            },
+            BinOp::Eq => {
+                // if rax == rdi, then set 1 to flag register
+                emit!(self, "    cmp rax, rdi");
+                // load the value of the flag register to al, which is lower 8 bits of rax
+                emit!(self, "    sete al");
+                // clear upper 56 bits with 0s
+                emit!(self, "    movzb rax, al");
+            },
+            BinOp::Ne => {
+                emit!(self, "    cmp rax, rdi");
+                emit!(self, "    setne al");
+                emit!(self, "    movzb rax, al");
+            },
+            BinOp::Lt => {
+                emit!(self, "    cmp rax, rdi");
+                if unsigned {
+                    emit!(self, "    setb al");
+                } else {
+                    emit!(self, "    setl al");
+                }
+                emit!(self, "    movzb rax, al");
+            },
+            BinOp::Le => {
+                emit!(self, "    cmp rax, rdi");
+                if unsigned {
+                    emit!(self, "    setbe al");
+                } else {
+                    emit!(self, "    setle al");
+                }
+                emit!(self, "    movzb rax, al");
+            },
+            BinOp::LogAnd | BinOp::LogOr => unreachable!("short-circuited in `gen`"),
+        }
+    }
 
+}
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+    use crate::Options;
+
+    fn compile(s: &str) -> Result<String, CompileError> {
+        crate::compile(s, &Options::default())
+    }
+
     #[test]
     fn test_compile() {
-        /*
-        CodeGenerator::from_str("((100 + 100)* 10) + 100").compile();
-        CodeGenerator::from_str("-5").compile();
-        CodeGenerator::from_str("123 +  (  + 33 - 99 )* 24").compile();
-        CodeGenerator::from_str("123 > 122").compile();
-        CodeGenerator::from_str("42 == 43").compile();
-        */
+        compile("int main() { ((100 + 100)* 10) + 100; }").unwrap();
+        let asm = compile("int main() { -5; }").unwrap();
+        assert!(asm.contains("    push 5\n    pop rax\n    neg rax\n    movsxd rax, eax\n    push rax\n"));
+        compile("int main() { 123 +  (  + 33 - 99 )* 24; }").unwrap();
+        compile("int main() { 123 > 122; }").unwrap();
+        compile("int main() { 42 == 43; }").unwrap();
+
+        let asm = compile("int main() { 1 + 2; }").unwrap();
+        assert_eq!(asm, "\
+.intel_syntax noprefix
+.text
+.global main
+main:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 0
+    push 1
+    push 2
+    pop rdi
+    pop rax
+    add rax, rdi
+    movsxd rax, eax
+    push rax
+    pop rax
+.L.return.main:
+    mov rsp, rbp
+    pop rbp
+    ret
+");
+
+        let asm = compile("int main() { 1; 2; }").unwrap();
+        assert!(asm.contains("\
+    push 1
+    pop rax
+    push 2
+    pop rax
+"));
+
+        let asm = compile("int main() { int a = 3; int b; b = a; }").unwrap();
+        assert!(asm.contains("    sub rsp, 16\n"));
+        assert!(asm.contains("    lea rax, [rbp-4]\n"));
+        assert!(asm.contains("    lea rax, [rbp-8]\n"));
+
+        // each control statement gets its own labels
+        let asm = compile("int main() { if (1) 2; else 3; while (0) 4; }").unwrap();
+        assert!(asm.contains("    je .L.else.1\n"));
+        assert!(asm.contains(".L.end.1:\n"));
+        assert!(asm.contains(".L.begin.2:\n"));
+        assert!(asm.contains("    jmp .L.begin.2\n"));
     }
 
-}
+    #[test]
+    fn test_function() {
+        let asm = compile("int f(int a, int b) { return a; } int main() { return f(1, 2); }").unwrap();
+        assert!(asm.contains(".global f\nf:\n"));
+        assert!(asm.contains("    mov [rbp-4], edi\n    mov [rbp-8], esi\n"));
+        assert!(asm.contains("    jmp .L.return.f\n"));
+        assert!(asm.contains(".global main\nmain:\n"));
+        assert!(asm.contains("    push 2\n    push 1\n    pop rdi\n    pop rsi\n"));
+        assert!(asm.contains("    mov eax, 0\n    call f\n"));
 
+        // arguments after the sixth are left on the stack, and rsp is aligned with the padding
+        let asm = compile("int main() { return f(1, 2, 3, 4, 5, 6, 7, 8); }").unwrap();
+        assert!(asm.contains("    push 8\n    push 7\n    push 6\n"));
+        assert!(asm.contains("    pop r9\n    mov eax, 0\n    call f\n    add rsp, 16\n"));
+        assert!(!asm.contains("sub rsp, 8"));
+        let asm = compile("int main() { return 1 + f(1, 2, 3, 4, 5, 6, 7); }").unwrap();
+        assert!(asm.contains("    push 1\n    push 7\n"));
+        assert!(asm.contains("    call f\n    add rsp, 8\n"));
+        let asm = compile("int main() { return 1 + f(1, 2, 3, 4, 5, 6, 7, 8); }").unwrap();
+        assert!(asm.contains("    push 1\n    sub rsp, 8\n    push 8\n"));
+        assert!(asm.contains("    call f\n    add rsp, 24\n"));
 
+        // variadic functions save the argument registers for `va_arg`
+        let asm = compile("int f(int n, ...) { va_list ap; va_start(ap, n); return va_arg(ap, int); }").unwrap();
+        assert!(asm.contains("    mov [rbp-184], rdi\n    mov [rbp-176], rsi\n"));
+        assert!(asm.contains("    mov [rbp-144], r9\n    movsd [rbp-136], xmm0\n"));
+        assert!(asm.contains("    movsd [rbp-24], xmm7\n"));
+        assert!(asm.contains("    mov dword ptr [rax], 8\n    mov dword ptr [rax+4], 48\n"));
+        assert!(asm.contains("    lea rdi, [rbp-184]\n    mov [rax+16], rdi\n"));
+        assert!(asm.contains(".L.end.1:\n    push rax\n    pop rax\n    movsxd rax, dword ptr [rax]\n"));
+    }
+
+    #[test]
+    fn test_pointer() {
+        let asm = compile("int main() { int x = 3; int *y = &x; return *y; }").unwrap();
+        // `int *y = &x;`
+        assert!(asm.contains("\
+    lea rax, [rbp-16]
+    push rax
+    lea rax, [rbp-4]
+    push rax
+    pop rdi
+    pop rax
+    mov [rax], rdi
+    push rdi
+    pop rax
+"));
+        // `*y`
+        assert!(asm.contains("    mov rax, [rax]\n    push rax\n    pop rax\n    movsxd rax, dword ptr [rax]\n"));
+
+        // arrays are not loaded, the value is the address
+        let asm = compile("int main() { int a[2]; int *p = a; }").unwrap();
+        assert!(asm.contains("    sub rsp, 16\n"));
+        assert!(asm.contains("    lea rax, [rbp-8]\n    push rax\n    pop rdi\n"));
+
+        // `p + 2` adds 2 * 8 bytes
+        let asm = compile("int main() { long *p; return *(p + 2); }").unwrap();
+        assert!(asm.contains("    push 2\n    push 8\n    pop rdi\n    pop rax\n    imul rax, rdi\n"));
+    }
+
+    #[test]
+    fn test_char() {
+        let asm = compile("int f(int a, char b) { char c = b; return c; }").unwrap();
+        assert!(asm.contains("    mov [rbp-4], edi\n    mov [rbp-5], sil\n"));
+        assert!(asm.contains("    movsx rax, byte ptr [rax]\n"));
+        assert!(asm.contains("    mov [rax], dil\n"));
+        assert!(asm.contains("    sub rsp, 16\n"));
+
+        let asm = compile(r#"int main() { "a\n"; return "b"[0]; }"#).unwrap();
+        assert!(asm.contains("    lea rax, [rip+.L.str.0]\n    push rax\n    pop rax\n"));
+        assert!(asm.ends_with("\
+.section .rodata
+.L.str.0:
+    .byte 97, 10, 0
+.L.str.1:
+    .byte 98, 0
+"));
+    }
+
+    #[test]
+    fn test_global() {
+        let asm = compile(r#"int x; int y = 3; char s[4] = "ab"; char *p = "cd"; int *q = &y + 1; int a[3] = {1}; int main() { return x + y; }"#).unwrap();
+        assert!(asm.starts_with("\
+.intel_syntax noprefix
+.bss
+.global x
+.align 4
+x:
+    .zero 4
+.data
+.global y
+.align 4
+y:
+    .byte 3, 0, 0, 0
+.data
+.global s
+.align 1
+s:
+    .byte 97, 98, 0, 0
+.data
+.global p
+.align 8
+p:
+    .quad .L.str.0
+.data
+.global q
+.align 8
+q:
+    .quad y+4
+.data
+.global a
+.align 4
+a:
+    .byte 1, 0, 0, 0
+    .zero 8
+.text
+"));
+        assert!(asm.contains("    lea rax, [rip+x]\n"));
+        assert!(asm.contains("    lea rax, [rip+y]\n"));
+        assert!(asm.ends_with(".section .rodata\n.L.str.0:\n    .byte 99, 100, 0\n"));
+    }
+
+    #[test]
+    fn test_integer() {
+        // loads extend values to 64 bits by the signedness
+        let asm = compile("int main() { short s; unsigned short us; unsigned char uc; unsigned u; long l; \
+            s; us; uc; u; l; }").unwrap();
+        assert!(asm.contains("    movsx rax, word ptr [rax]\n"));
+        assert!(asm.contains("    movzx eax, word ptr [rax]\n"));
+        assert!(asm.contains("    movzx eax, byte ptr [rax]\n"));
+        assert!(asm.contains("    mov eax, [rax]\n"));
+        assert!(asm.contains("    mov rax, [rax]\n"));
+
+        // stores truncate values
+        let asm = compile("int f(short a, long b) { a = 1; b = 2; }").unwrap();
+        assert!(asm.contains("    mov [rbp-2], di\n    mov [rbp-16], rsi\n"));
+        assert!(asm.contains("    mov [rax], di\n"));
+        assert!(asm.contains("    mov [rax], rdi\n"));
+
+        // unsigned division and comparison
+        let asm = compile("int main() { unsigned a; a / 2; a < 1; a <= 1; }").unwrap();
+        assert!(asm.contains("    mov edx, 0\n    div rdi\n    mov eax, eax\n"));
+        assert!(asm.contains("    setb al\n"));
+        assert!(asm.contains("    setbe al\n"));
+        let asm = compile("int main() { int a; a / 2; a < 1; }").unwrap();
+        assert!(asm.contains("    cqo\n    idiv rdi\n    movsxd rax, eax\n"));
+        assert!(asm.contains("    setl al\n"));
+
+        // casts truncate and extend the value again
+        let asm = compile("int main() { long l; (char)l; (unsigned short)l; (unsigned char)l; (int)l; (unsigned)l; }").unwrap();
+        assert!(asm.contains("    pop rax\n    movsx rax, al\n    push rax\n"));
+        assert!(asm.contains("    pop rax\n    movzx eax, ax\n    push rax\n"));
+        assert!(asm.contains("    pop rax\n    movzx eax, al\n    push rax\n"));
+        assert!(asm.contains("    pop rax\n    movsxd rax, eax\n    push rax\n"));
+        assert!(asm.contains("    pop rax\n    mov eax, eax\n    push rax\n"));
+
+        // return values of calls are extended
+        let asm = compile("int main() { return f(); }").unwrap();
+        assert!(asm.contains("    call f\n    movsxd rax, eax\n    push rax\n"));
+
+        // numbers out of the range of 32 bits
+        let asm = compile("int main() { 4294967295; (unsigned)-1; }").unwrap();
+        assert!(asm.contains("    movabs rax, 4294967295\n    push rax\n"));
+        assert!(!asm.contains("    push 4294967295\n"));
+    }
+
+    #[test]
+    fn test_operators() {
+        let asm = compile("int main() { int a; unsigned u; a % 3; u % 3; a >> 1; u >> 1; a << 1; }").unwrap();
+        assert!(asm.contains("    cqo\n    idiv rdi\n    mov rax, rdx\n"));
+        assert!(asm.contains("    mov edx, 0\n    div rdi\n    mov rax, rdx\n"));
+        assert!(asm.contains("    mov rcx, rdi\n    sar rax, cl\n"));
+        assert!(asm.contains("    mov rcx, rdi\n    shr rax, cl\n"));
+        assert!(asm.contains("    mov rcx, rdi\n    shl rax, cl\n"));
+
+        // `&&` and `||` jump over the right operand
+        let asm = compile("int main() { return 1 && 2; }").unwrap();
+        assert!(asm.contains("    je .L.false.1\n    push 2\n    pop rax\n    cmp rax, 0\n    je .L.false.1\n"));
+        assert!(asm.contains("    push 1\n    jmp .L.end.1\n.L.false.1:\n    push 0\n.L.end.1:\n"));
+        let asm = compile("int main() { return 1 || 2; }").unwrap();
+        assert!(asm.contains("    jne .L.true.1\n    push 0\n    jmp .L.end.1\n.L.true.1:\n    push 1\n.L.end.1:\n"));
+
+        let asm = compile("int main() { return 1 ? 2 : 3; }").unwrap();
+        assert!(asm.contains("    je .L.else.1\n    push 2\n    jmp .L.end.1\n.L.else.1:\n    push 3\n.L.end.1:\n"));
+        let asm = compile("int main() { return (1, 2); }").unwrap();
+        assert!(asm.contains("    push 1\n    pop rax\n    push 2\n"));
+    }
+
+    #[test]
+    fn test_struct() {
+        let asm = compile("int main() { struct {char a; short b;} x, y; x.b = 1; x = y; return y.b; }").unwrap();
+        // `x.b`
+        assert!(asm.contains("    lea rax, [rbp-4]\n    push rax\n    pop rax\n    add rax, 2\n    push rax\n"));
+        // `x = y` copies 4 bytes
+        assert!(asm.contains("\
+    lea rax, [rbp-4]
+    push rax
+    lea rax, [rbp-8]
+    push rax
+    pop rdi
+    pop rax
+    mov r8b, [rdi+0]
+    mov [rax+0], r8b
+    mov r8b, [rdi+1]
+    mov [rax+1], r8b
+    mov r8b, [rdi+2]
+    mov [rax+2], r8b
+    mov r8b, [rdi+3]
+    mov [rax+3], r8b
+    push rdi
+"));
+
+        let asm = compile("struct {long a; int b;} g; int main() { return g.b; }").unwrap();
+        assert!(asm.contains(".align 8\ng:\n    .zero 16\n"));
+        assert!(asm.contains("    lea rax, [rip+g]\n    push rax\n    pop rax\n    add rax, 8\n"));
+    }
+
+    #[test]
+    fn test_compile_error() {
+        assert!(compile("int main() { 1 < }").is_err());
+        assert!(compile("int main() { 1 + $; }").is_err());
+        let err = compile("int main() { 1 = 2; }").unwrap_err();
+        assert_eq!(err.message(), "not an lvalue");
+        assert_eq!(err.location().unwrap().column, 14);
+        let err = compile("int main() { return x; }").unwrap_err();
+        assert_eq!(err.message(), "undeclared variable `x`");
+    }
+
+    // sink that accepts only `limit` bytes
+    struct Limited {
+        written: usize,
+        limit: usize,
+    }
+
+    impl fmt::Write for Limited {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.written += s.len();
+            if self.written > self.limit { Err(fmt::Error) } else { Ok(()) }
+        }
+    }
+
+    #[test]
+    fn test_output() {
+        let program = crate::check("int main() { return 0; }", &Options::default()).unwrap();
+        let mut asm = String::new();
+        CodeGenerator::new(&mut asm).generate(&program).unwrap();
+        assert!(asm.starts_with(".intel_syntax noprefix\n"));
+
+        let mut generator = CodeGenerator::new(Limited {written: 0, limit: asm.len()});
+        generator.generate(&program).unwrap();
+        assert_eq!(generator.into_output().written, asm.len());
+        let err = CodeGenerator::new(Limited {written: 0, limit: 10}).generate(&program).unwrap_err();
+        assert_eq!(err.message(), "cannot write the assembly");
+    }
+
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+
+// Position of an error in the source string.
+// `line` and `column` are 1-origin, `column` is counted in chars.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    // the whole line containing `offset`, without the line break
+    pub source_line: String,
+}
+
+impl Location {
+    pub fn new(source: &str, offset: usize) -> Self {
+        let offset = offset.min(source.len());
+        let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[offset..].find('\n').map_or(source.len(), |i| offset + i);
+        let line = source[..line_start].matches('\n').count() + 1;
+        let column = source[line_start..offset].chars().count() + 1;
+        Self {
+            offset,
+            line,
+            column,
+            source_line: source[line_start..line_end].to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    message: String,
+    location: Option<Location>,
+    file_name: Option<String>,
+}
+
+impl CompileError {
+    // error without a position in the source
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            location: None,
+            file_name: None,
+        }
+    }
+
+    // error at byte `offset` of `source`
+    pub fn at(source: &str, offset: usize, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            location: Some(Location::new(source, offset)),
+            file_name: None,
+        }
+    }
+
+    // set the name of the source file shown in the message
+    pub fn in_file(mut self, name: &str) -> Self {
+        self.file_name = Some(name.to_string());
+        self
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn location(&self) -> Option<&Location> {
+        self.location.as_ref()
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+}
+
+// Rendered like below, with the caret under the error position.
+//
+// foo.c:1: 1 + * 2;
+//                ^ expected an expression
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Some(loc) => {
+                let prefix = match &self.file_name {
+                    Some(name) => format!("{}:{}: ", name, loc.line),
+                    None => format!("{}: ", loc.line),
+                };
+                writeln!(f, "{}{}", prefix, loc.source_line)?;
+                // keep tabs so that the caret lines up with the source line
+                let indent: String = prefix.chars()
+                    .chain(loc.source_line.chars().take(loc.column - 1))
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                write!(f, "{}^ {}", indent, self.message)
+            },
+            None => match &self.file_name {
+                Some(name) => write!(f, "{}: error: {}", name, self.message),
+                None => write!(f, "error: {}", self.message),
+            },
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location() {
+        let src = "a = 1;\nb = a +;\n";
+        let loc = Location::new(src, 14);
+        assert_eq!(loc.line, 2);
+        assert_eq!(loc.column, 8);
+        assert_eq!(loc.source_line, "b = a +;");
+
+        let loc = Location::new(src, 0);
+        assert_eq!((loc.line, loc.column), (1, 1));
+        assert_eq!(loc.source_line, "a = 1;");
+
+        // past the end of the input
+        let loc = Location::new("1 <", 3);
+        assert_eq!((loc.line, loc.column), (1, 4));
+    }
+
+    #[test]
+    fn display() {
+        let err = CompileError::at("1 + * 2;", 4, "expected an expression");
+        assert_eq!(err.to_string(), "1: 1 + * 2;\n       ^ expected an expression");
+
+        let err = CompileError::at("\tfoo $", 5, "invalid token");
+        assert_eq!(err.to_string(), "1: \tfoo $\n   \t    ^ invalid token");
+
+        let err = CompileError::at("1;\n2 +;", 6, "expected an expression").in_file("foo.c");
+        assert_eq!(err.to_string(), "foo.c:2: 2 +;\n            ^ expected an expression");
+
+        let err = CompileError::new("something wrong");
+        assert_eq!(err.to_string(), "error: something wrong");
+        assert_eq!(err.in_file("-").to_string(), "-: error: something wrong");
+    }
+}
@@ -1,402 +1,609 @@
-use crate::node::{Node, NodeKind};
-use crate::utils::Consumer;
+use std::fmt;
+use crate::error::CompileError;
+use crate::types::Type;
+use crate::utils::{is_space, Consumer};
 
-/*
+// Multi-char punctuators must come before their prefixes.
+const PUNCTUATORS: [&str; 48] = [
+    "<<=", ">>=", "...",
+    "##", "==", "!=", "<=", ">=", "->", "<<", ">>", "&&", "||", "++", "--",
+    "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=",
+    "+", "-", "*", "/", "%", "(", ")", "{", "}", "[", "]", "<", ">", "=", ";", ",",
+    "&", "|", "^", "~", "!", "?", ":", ".", "#",
+];
 
-program = stmt*
-stmt = expr ";"
-expr = assign
-assign = equality ("=" assign)?
-equality = relational ("==" relational | "!=" relational)*
-relational = add ("<" add | "<=" add | ">" add | ">=" add)*
-add = mul ("+" mul | "-" mul)*
-mul = unary ("*" unary | "/" unary)*
-unary = ("+" | "-")? primary
-primary = num | ident | "(" expr ")"
+const KEYWORDS: [&str; 16] = [
+    "return", "if", "else", "while", "for", "sizeof", "_Alignof",
+    "char", "short", "int", "long", "signed", "unsigned", "struct", "union", "va_list",
+];
 
-*/
+// Byte range `start..end` in the source file `file`, which is an index of `SourceMap`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub file: usize,
+    pub start: usize,
+    pub end: usize,
+}
 
+impl Span {
+    // span in the first file
+    pub fn new(start: usize, end: usize) -> Self {
+        Self {file: 0, start, end}
+    }
 
-//TODO
-// original Input
-// 1. peek(n)
-// 2. next(n)
-// 3. inputtou(&mut Input)
+    // span from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Self {
+        Self {end: other.end, ..self}
+    }
+}
 
-// Input makes node tree from string
-// pub struct Input<'a> {
-//     input: Peekable<Chars<'a>>,
-// }
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    // integer literal with the type given by its form and suffix
+    Num(u64, Type),
+    // character literal `'a'`
+    Char(u8),
+    // string literal, without the terminating NUL
+    Str(Vec<u8>),
+    Ident(String),
+    Keyword(String),
+    Punct(String),
+    Eof,
+}
 
-pub struct Input {
-    input: Consumer,
+// C source text of the token
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // the suffix keeps the type
+            TokenKind::Num(n, ty) => {
+                let suffix = match ty {
+                    Type::UInt => "u",
+                    Type::Long => "l",
+                    Type::ULong => "ul",
+                    _ => "",
+                };
+                write!(f, "{}{}", n, suffix)
+            },
+            TokenKind::Char(c) => write!(f, "'{}'", escape(&[*c], b'\'')),
+            TokenKind::Str(bytes) => write!(f, "\"{}\"", escape(bytes, b'"')),
+            TokenKind::Ident(s) | TokenKind::Keyword(s) | TokenKind::Punct(s) => write!(f, "{}", s),
+            TokenKind::Eof => Ok(()),
+        }
+    }
 }
 
-impl Input {
-    pub fn new(input: &str) -> Self {
-        // let iter = input.chars().peekable();
-        let consumer = Consumer::new(input);
-        Self {input: consumer}
+// Escape `bytes` to be put in quotes `quote`.
+// Bytes other than printable ASCII are written in octal, which takes at most 3 digits.
+fn escape(bytes: &[u8], quote: u8) -> String {
+    let mut s = String::new();
+    for &b in bytes {
+        match b {
+            b'\\' => s.push_str("\\\\"),
+            b if b == quote => {
+                s.push('\\');
+                s.push(b as char);
+            },
+            b'\n' => s.push_str("\\n"),
+            b'\t' => s.push_str("\\t"),
+            b'\r' => s.push_str("\\r"),
+            b' '..=b'~' => s.push(b as char),
+            _ => s.push_str(&format!("\\{:03o}", b)),
+        }
     }
+    s
+}
 
-    pub fn tokenize(&mut self) -> Vec<Node> {
-        let head_node = self.program();
-        head_node
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    kind: TokenKind,
+    span: Span,
+    // the first token of a line
+    at_bol: bool,
+    // preceded by white spaces or comments
+    has_space: bool,
+    // names of the macros expanded to this token, not to be expanded again
+    hideset: Vec<String>,
+}
+
+impl Token {
+    pub fn new(kind: TokenKind, span: Span) -> Self {
+        Self {kind, span, at_bol: false, has_space: false, hideset: Vec::new()}
     }
 
+    pub fn kind(&self) -> &TokenKind {
+        &self.kind
+    }
 
-    // program = stmt*
-    fn program(&mut self) -> Vec<Node> {
-        let mut program = Vec::new();
-        loop {
-            self.input.skip_space();
-            let stmt = self.stmt();
-            program.push(stmt);
-            self.input.skip_space();
-            if self.input.peek().is_none() {
-                break;
-            }
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn at_bol(&self) -> bool {
+        self.at_bol
+    }
+
+    pub fn has_space(&self) -> bool {
+        self.has_space
+    }
+
+    pub fn hideset(&self) -> &[String] {
+        &self.hideset
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = span;
+    }
+
+    // set the spacing flags `at_bol` and `has_space`
+    pub fn set_spacing(&mut self, at_bol: bool, has_space: bool) {
+        self.at_bol = at_bol;
+        self.has_space = has_space;
+    }
+
+    pub fn set_hideset(&mut self, hideset: Vec<String>) {
+        self.hideset = hideset;
+    }
+
+    // the name of the identifier, where keywords are also identifiers
+    pub fn ident(&self) -> Option<&str> {
+        match &self.kind {
+            TokenKind::Ident(s) | TokenKind::Keyword(s) => Some(s),
+            _ => None,
         }
-        program
-    }
-
-    // stmt = expr ";"
-    fn stmt(&mut self) -> Node {
-        self.input.skip_space();
-        let expr = self.expr();
-        self.input.skip_space();
-        match self.input.peek() {
-            Some(s) => {
-                if s == ";" {
-                    self.input.next();
-                    expr
-                } else {
-                    panic!("expected `;`, but found `{}`", s);
-                }
-            },
-            None => {
-                expr
-            }
+    }
+
+    // true if the token is the punctuator or keyword `s`
+    pub fn is(&self, s: &str) -> bool {
+        match &self.kind {
+            TokenKind::Punct(p) | TokenKind::Keyword(p) => p == s,
+            _ => false,
         }
     }
+}
+
+// Lexer splits source string into tokens
+pub struct Lexer<'a> {
+    source: &'a str,
+    input: Consumer,
+    // file id of spans
+    file: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self::for_file(input, 0)
+    }
+
+    // lexer of the source file `file` of `SourceMap`
+    pub fn for_file(input: &'a str, file: usize) -> Self {
+        let consumer = Consumer::new(input);
+        Self {source: input, input: consumer, file}
+    }
 
-    // expr = assign
-    fn expr(&mut self) -> Node {
-        self.input.skip_space();
-        self.assign()
-    }
-
-    // assign = equality ("=" assign)?
-    fn assign(&mut self) -> Node {
-        self.input.skip_space();
-        let node = self.equality();
-        self.input.skip_space();
-        match self.input.peek() {
-            Some(s) => {
-                if s == "=" {
-                    self.input.next();
-                    Node::new(
-                        NodeKind::Op("=".to_string()),
-                        Node::link(node),
-                        Node::link(self.assign()),
-                    )
+    fn span(&self, start: usize) -> Span {
+        Span {file: self.file, start, end: self.input.offset()}
+    }
+
+    // Return tokens of the whole input. The last token is always `TokenKind::Eof`.
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, CompileError> {
+        let mut tokens = Vec::new();
+        let mut at_bol = true;
+        loop {
+            let (newline, has_space) = self.skip_space_and_comments()?;
+            at_bol |= newline;
+            let start = self.input.offset();
+            let c = match self.input.peek_char() {
+                Some(c) => c,
+                None => {
+                    tokens.push(Token::new(TokenKind::Eof, self.span(start)));
+                    return Ok(tokens);
+                }
+            };
+
+            let kind = if c.is_ascii_digit() {
+                self.number(start)?
+            } else if is_ident_head(c) {
+                let ident = self.input.next_while(is_ident_tail);
+                if KEYWORDS.contains(&ident.as_str()) {
+                    TokenKind::Keyword(ident)
                 } else {
-                    node
-                    // panic!("expected `=`, but found `{}`", c);
+                    TokenKind::Ident(ident)
                 }
-            },
-            None => {
-                node
-            }
+            } else if c == '"' {
+                TokenKind::Str(self.string_literal(start)?)
+            } else if c == '\'' {
+                TokenKind::Char(self.char_literal(start)?)
+            } else if let Some(punct) = self.punctuator() {
+                TokenKind::Punct(punct)
+            } else {
+                return Err(CompileError::at(self.source, start, "invalid token"));
+            };
+            let mut token = Token::new(kind, self.span(start));
+            token.set_spacing(at_bol, has_space);
+            tokens.push(token);
+            at_bol = false;
         }
-
     }
-    
-    // equality = relational ("==" relational | "!=" relational)*
-    fn equality(&mut self) -> Node {
-        let mut node = self.relational();
 
+    // Skip white spaces, `// ...` and `/* ... */`.
+    // Return whether a line break and whether anything is skipped.
+    // A backslash at the end of a line joins the lines.
+    fn skip_space_and_comments(&mut self) -> Result<(bool, bool), CompileError> {
+        let start = self.input.offset();
+        let mut newline = false;
         loop {
-            self.input.skip_space();
-            match self.input.peek_n(2) {
-                Some(s) => {
-                    match &s as &str {
-                        "==" => {
-                            self.input.next_n(2);
-                            node = Node::new(
-                                NodeKind::Op("==".to_string()),
-                                Node::link(node),
-                                Node::link(self.relational()),
-                            );
-                        },
-                        "!=" => {
+            newline |= self.input.next_while(is_space).contains('\n');
+            match self.input.peek_n(2).as_deref() {
+                Some("\\\n") => {
+                    self.input.next_n(2);
+                },
+                Some("//") => {
+                    self.input.next_while(|c| c != '\n');
+                },
+                Some("/*") => {
+                    let start = self.input.offset();
+                    self.input.next_n(2);
+                    loop {
+                        if self.input.peek_n(2).as_deref() == Some("*/") {
                             self.input.next_n(2);
-                            node = Node::new(
-                                NodeKind::Op("!=".to_string()),
-                                Node::link(node),
-                                Node::link(self.relational()),
-                            );
+                            break;
                         }
-                        _ => {
-                            return node;
+                        if self.input.next_char().is_none() {
+                            return Err(CompileError::at(self.source, start, "unterminated comment"));
                         }
                     }
                 },
-                None => {
-                    return node;
-                }
+                _ => return Ok((newline, self.input.offset() > start)),
             }
         }
     }
-    
-    // relational = add ("<" add | "<=" add | ">" add | ">=" add)*
-    fn relational(&mut self) -> Node {
-        let mut node = self.add();
 
-        loop {
-            self.input.skip_space();
-            match self.input.peek() {
-                Some(s) => {
-                    match &s as &str {
-                        "<" => {
-                            self.input.next();
-                            if self.input.peek().unwrap() == "=" {
-                                self.input.next();
-                                node = Node::new(
-                                    NodeKind::Op("<=".to_string()), 
-                                    Node::link(node), 
-                                    Node::link(self.add()),
-                                );
-                            } else {
-                                node = Node::new(
-                                    NodeKind::Op("<".to_string()), 
-                                    Node::link(node), 
-                                    Node::link(self.add()),
-                                );
-                            }
-                        }
-                        ">" => {
-                            self.input.next();
-                            // instead of A > B, implement B < A
-                            if self.input.peek().unwrap() == "=" {
-                                self.input.next();
-                                node = Node::new(
-                                    NodeKind::Op("<=".to_string()), 
-                                    Node::link(self.add()), 
-                                    Node::link(node),
-                                );
-                            } else {
-                                node = Node::new(
-                                    NodeKind::Op("<".to_string()), 
-                                    Node::link(self.add()), 
-                                    Node::link(node),
-                                );
-                            }
-                        }
-                        _ => {
-                            // panic!("invalid operator {}, expected `<` or `>`", c);
-                            return node;
-                        }
-                    }
-                }
-                None => {
-                    return node;
-                }
-            }
+    // Read an integer literal starting at `start`.
+    // Its type is the first of the candidates below that can represent the value.
+    //
+    //   suffix     decimal              hexadecimal, octal or binary
+    //   none       int, long            int, unsigned, long, unsigned long
+    //   u          unsigned, unsigned long
+    //   l, ll      long                 long, unsigned long
+    //   ul, ull    unsigned long
+    //
+    // `long long` is the same as `long`.
+    fn number(&mut self, start: usize) -> Result<TokenKind, CompileError> {
+        // read letters too, to reject literals like `12ab` as a whole
+        let text = self.input.next_while(is_ident_tail);
+        let invalid = || CompileError::at(self.source, start, "invalid integer literal");
+        let (radix, body) = match text.get(..2) {
+            Some("0x") | Some("0X") => (16, &text[2..]),
+            Some("0b") | Some("0B") => (2, &text[2..]),
+            _ if text.starts_with('0') => (8, &text[..]),
+            _ => (10, &text[..]),
+        };
+        let len = body.find(|c: char| !c.is_digit(radix)).unwrap_or(body.len());
+        let (digits, suffix) = body.split_at(len);
+        if digits.is_empty() {
+            return Err(invalid());
+        }
+
+        // suffixes are case-insensitive, except that `ll` must be `ll` or `LL`
+        if suffix.contains("lL") || suffix.contains("Ll") {
+            return Err(invalid());
         }
+        let (unsigned, long) = match suffix.to_ascii_lowercase().as_str() {
+            "" => (false, false),
+            "u" => (true, false),
+            "l" | "ll" => (false, true),
+            "ul" | "lu" | "ull" | "llu" => (true, true),
+            _ => return Err(invalid()),
+        };
+        let too_large = || CompileError::at(self.source, start, "integer literal is too large");
+        let value = digits.chars()
+            .try_fold(0u64, |value, c| value.checked_mul(radix as u64)?.checked_add(c.to_digit(radix)? as u64))
+            .ok_or_else(too_large)?;
+
+        let fits_int = !long && value <= i32::MAX as u64;
+        let fits_uint = !long && value <= u32::MAX as u64;
+        let fits_long = value <= i64::MAX as u64;
+        let ty = match (unsigned, radix == 10) {
+            (false, _) if fits_int => Type::Int,
+            (false, false) if fits_uint => Type::UInt,
+            (false, _) if fits_long => Type::Long,
+            (false, false) => Type::ULong,
+            // a decimal literal without `u` is never unsigned
+            (false, true) => return Err(too_large()),
+            (true, _) if fits_uint => Type::UInt,
+            (true, _) => Type::ULong,
+        };
+        Ok(TokenKind::Num(value, ty))
     }
-    
-    // add = mul ('+' mul | '-' mul)*
-    fn add(&mut self) -> Node {
-        let mut node = self.mul();
 
+    // read `"..."` starting at `start`
+    fn string_literal(&mut self, start: usize) -> Result<Vec<u8>, CompileError> {
+        self.input.next_char();
+        let mut bytes = Vec::new();
         loop {
-            self.input.skip_space();
-            match self.input.peek() {
-                Some(s) => {
-                    match &s as &str {
-                        "+" => {
-                            self.input.next();
-                            node = Node::new(
-                                NodeKind::Op("+".to_string()), 
-                                Node::link(node), 
-                                Node::link(self.mul()),
-                            );
-                        },
-                        "-" => {
-                            self.input.next();
-                            node = Node::new(
-                                NodeKind::Op("-".to_string()), 
-                                Node::link(node), 
-                                Node::link(self.mul()),
-                            );
-                        },
-                        _ => {
-                            return node;
-                        },
-                    }
+            match self.input.next_char() {
+                Some('"') => return Ok(bytes),
+                Some('\\') => bytes.push(self.escaped_char()?),
+                Some('\n') | None => {
+                    return Err(CompileError::at(self.source, start, "unclosed string literal"));
+                },
+                Some(c) => {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
                 },
-                None => {
-                    return node;
-                }
             }
         }
+    }
 
+    // read `'c'` starting at `start`
+    fn char_literal(&mut self, start: usize) -> Result<u8, CompileError> {
+        self.input.next_char();
+        let c = match self.input.next_char() {
+            Some('\\') => self.escaped_char()?,
+            Some('\'') => return Err(CompileError::at(self.source, start, "empty char literal")),
+            Some(c) if c.is_ascii() && c != '\n' => c as u8,
+            Some('\n') | None => {
+                return Err(CompileError::at(self.source, start, "unclosed char literal"));
+            },
+            Some(_) => {
+                return Err(CompileError::at(self.source, start, "multibyte char literal is not supported"));
+            },
+        };
+        if self.input.next_char() != Some('\'') {
+            return Err(CompileError::at(self.source, start, "unclosed char literal"));
+        }
+        Ok(c)
     }
-    
-    // mul = uary ('*' unary | '/' uary)*
-    fn mul(&mut self) -> Node {
-        let mut node = self.unary();
 
-        loop {
-            self.input.skip_space();
-            match self.input.peek() {
-                Some(s) => {
-                    match &s as &str {
-                        "*" => {
-                            self.input.next();
-                            node = Node::new(
-                                NodeKind::Op("*".to_string()), 
-                                Node::link(node), 
-                                Node::link(self.unary()),
-                            );
-                            
-                        },
-                        "/" => {
-                            self.input.next();
-                            node = Node::new(
-                                NodeKind::Op("/".to_string()), 
-                                Node::link(node), 
-                                Node::link(self.unary()),
-                                );
-                        },
-                        _ => {
-                            return node;
-                        }
-                    }
-                },
-                None => {
-                    return node;
+    // read an escape sequence after `\`
+    fn escaped_char(&mut self) -> Result<u8, CompileError> {
+        let start = self.input.offset();
+        let c = match self.input.peek_char() {
+            Some(c) => c,
+            // the literal is unclosed, which the caller reports
+            None => return Ok(0),
+        };
+
+        // octal `\ooo`, at most 3 digits
+        if c.is_digit(8) {
+            let mut value: u32 = 0;
+            for _ in 0..3 {
+                match self.input.peek_char().and_then(|c| c.to_digit(8)) {
+                    Some(d) => value = value * 8 + d,
+                    None => break,
                 }
+                self.input.next_char();
             }
+            return Ok(value as u8);
         }
-    }
 
-    // unary = ('+' | '-')? primary
-    fn unary(&mut self) -> Node {
-        self.input.skip_space();
-        match self.input.peek() {
-            Some(s) => {
-                match &s as &str {
-                    "+" => {
-                        self.input.next();
-                        return self.primary();
-                    },
-                    "-" => {
-                        self.input.next();
-                        // returns 0 - primary
-                        return Node::new(
-                            NodeKind::Op("-".to_string()), 
-                            Node::link(Node::new(NodeKind::Num(0), None, None)),
-                            Node::link(self.primary()),
-                        );
-                    },
-                    _ => {
-                        return self.primary();
-                    }
-                }
-            },
-            None => {
-                panic!("Expected value: found None");
+        self.input.next_char();
+        // hexadecimal `\xhh...`, as many digits as follow
+        if c == 'x' {
+            let digits = self.input.next_while(|c| c.is_ascii_hexdigit());
+            if digits.is_empty() {
+                return Err(CompileError::at(self.source, start, "invalid hex escape sequence"));
             }
+            let value = digits.chars()
+                .fold(0u8, |value, c| value.wrapping_mul(16).wrapping_add(c.to_digit(16).unwrap() as u8));
+            return Ok(value);
+        }
+
+        match c {
+            'a' => Ok(7),
+            'b' => Ok(8),
+            't' => Ok(b'\t'),
+            'n' => Ok(b'\n'),
+            'v' => Ok(11),
+            'f' => Ok(12),
+            'r' => Ok(b'\r'),
+            // [GNU] `\e` is the escape character
+            'e' => Ok(27),
+            // other characters stand for themselves, like `\\` and `\"`
+            c if c.is_ascii() => Ok(c as u8),
+            _ => Err(CompileError::at(self.source, start, "invalid escape sequence")),
         }
     }
-    
-    // primary = num | ident | '(' expr ')'
-    fn primary(&mut self) -> Node {
-        self.input.skip_space();
-        match self.input.peek() {
-            Some(s) => {
-                if s.chars().all(|c| char::is_numeric(c)) {
-                    let num = self.input.to_usize().unwrap();
-                    return Node::new(
-                        NodeKind::Num(num),
-                        None,
-                        None,
-                    );
-                }
-                if s == "(" {
-                    self.input.next();
-                    let node = self.expr();
-                    self.input.skip_space();
-                    if self.input.peek().unwrap() == ")" {
-                        self.input.next();
-                    } else {
-                        panic!("expected `)`, but not found");
-                    }
-                    return node;
-                }
-                if s.chars().all(|c| char::is_alphabetic(c)) {
-                    self.input.next();
-                    let ident = s;
-                    return Node::new(
-                        NodeKind::LVar(ident.to_string()),
-                        None,
-                        None,
-                    );
-                }
-                panic!("invalid element: {}", s);
-            },
-            None => {
-                panic!("Expected some value!");
+
+    // read the longest punctuator at the current position
+    fn punctuator(&mut self) -> Option<String> {
+        for p in PUNCTUATORS.iter() {
+            if self.input.peek_n(p.len()).as_deref() == Some(p) {
+                return self.input.next_n(p.len());
             }
         }
+        None
     }
 }
 
+fn is_ident_head(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_tail(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    fn kinds(s: &str) -> Vec<TokenKind> {
+        Lexer::new(s).tokenize().unwrap().into_iter().map(|t| t.kind).collect()
+    }
+
+    fn punct(s: &str) -> TokenKind {
+        TokenKind::Punct(s.to_string())
+    }
+
+    // `int` literal
+    fn num(n: u64) -> TokenKind {
+        TokenKind::Num(n, Type::Int)
+    }
+
     #[test]
-    fn test_node() {
-        test_tokenize("1*(2+3);");
-        test_tokenize("1 + 20+ 4;");
-        test_tokenize(" 9- 6 * 10;");
-        test_tokenize("1-10/100 +1000 * 10000;");
-        test_tokenize("((2-20)*200 + 2000)*(21 - 201);");
-        test_tokenize("((100 + 100)* 10) + 100;");
-        test_tokenize("1 == 1;");
-        test_tokenize("1 != 1;");
-        test_tokenize("1 <= 1;");
-        test_tokenize("1 >= 1;");
-        test_tokenize("1 < 1;");
-        test_tokenize("1 > 1;");
-        test_tokenize("1 == 1 == 1;");
-        test_tokenize("1 > 1 > 1;");
-        test_tokenize("a = 1;");
-        test_tokenize("a = 1 + 3;");
-        test_tokenize("a = b * 3 - p;");
-    }
-    
-
-    fn print_node(node: &Node) {
-        println!("{:?}", node.kind());
-        if let Some(n) = node.lhs() {
-            print_node(n);
-        }
-        
-        if let Some(n) = node.rhs() {
-            print_node(n);
-        }
+    fn tokenize_expr() {
+        assert_eq!(kinds("1+ 20 *(3)"), vec![
+            num(1), punct("+"), num(20), punct("*"),
+            punct("("), num(3), punct(")"), TokenKind::Eof,
+        ]);
+        assert_eq!(kinds("1<=2>3!=4==5>=6"), vec![
+            num(1), punct("<="), num(2), punct(">"),
+            num(3), punct("!="), num(4), punct("=="),
+            num(5), punct(">="), num(6), TokenKind::Eof,
+        ]);
+        assert_eq!(kinds("a<<=b>>c&&d||e++--f"), vec![
+            TokenKind::Ident("a".to_string()), punct("<<="), TokenKind::Ident("b".to_string()),
+            punct(">>"), TokenKind::Ident("c".to_string()), punct("&&"),
+            TokenKind::Ident("d".to_string()), punct("||"), TokenKind::Ident("e".to_string()),
+            punct("++"), punct("--"), TokenKind::Ident("f".to_string()), TokenKind::Eof,
+        ]);
+        assert_eq!(kinds("%=%!~?:"), vec![
+            punct("%="), punct("%"), punct("!"), punct("~"), punct("?"), punct(":"), TokenKind::Eof,
+        ]);
+        assert_eq!(kinds(""), vec![TokenKind::Eof]);
     }
 
-    fn test_tokenize(s: &str) {
-        let mut input = Input::new(s);
-        let head = input.tokenize();
-        for h in head {
-            print_node(&h);
-        }
-        println!("------------");
+    #[test]
+    fn tokenize_ident() {
+        assert_eq!(kinds("foo_1 = a;return returned"), vec![
+            TokenKind::Ident("foo_1".to_string()), punct("="),
+            TokenKind::Ident("a".to_string()), punct(";"),
+            TokenKind::Keyword("return".to_string()),
+            TokenKind::Ident("returned".to_string()), TokenKind::Eof,
+        ]);
+        assert_eq!(kinds("p->x.y-z"), vec![
+            TokenKind::Ident("p".to_string()), punct("->"), TokenKind::Ident("x".to_string()),
+            punct("."), TokenKind::Ident("y".to_string()), punct("-"),
+            TokenKind::Ident("z".to_string()), TokenKind::Eof,
+        ]);
     }
-}
 
+    #[test]
+    fn tokenize_span() {
+        let tokens = Lexer::new(" 12 +\tabc").tokenize().unwrap();
+        let spans: Vec<Span> = tokens.iter().map(|t| t.span()).collect();
+        assert_eq!(spans, vec![
+            Span::new(1, 3), Span::new(4, 5), Span::new(6, 9), Span::new(9, 9),
+        ]);
+    }
+
+    #[test]
+    fn tokenize_comment() {
+        assert_eq!(kinds("1\n+\r\n2\x0c\x0b3"), vec![num(1), punct("+"), num(2), num(3), TokenKind::Eof]);
+        assert_eq!(kinds("1 // 2 /* 3\n4 /* 5 // 6\n */ 7/**/8"), vec![num(1), num(4), num(7), num(8), TokenKind::Eof]);
+        // comments are not recognized in literals
+        assert_eq!(kinds(r#""/*" 1 / /2"#), vec![
+            TokenKind::Str(b"/*".to_vec()), num(1), punct("/"), punct("/"), num(2), TokenKind::Eof,
+        ]);
+        assert_eq!(kinds("// only a comment"), vec![TokenKind::Eof]);
+
+        let tokens = Lexer::new("/* a */\n  x").tokenize().unwrap();
+        assert_eq!(tokens[0].span(), Span::new(10, 11));
+
+        let err = Lexer::new("1;\n /* 2;\n3;").tokenize().unwrap_err();
+        assert_eq!(err.message(), "unterminated comment");
+        let loc = err.location().unwrap();
+        assert_eq!((loc.line, loc.column), (2, 2));
+    }
+
+    #[test]
+    fn tokenize_spacing() {
+        let tokens = Lexer::new("# define\n  x/**/y z\\\n(").tokenize().unwrap();
+        let flags: Vec<(bool, bool)> = tokens.iter().map(|t| (t.at_bol(), t.has_space())).collect();
+        assert_eq!(flags, vec![
+            (true, false), (false, true), (true, true), (false, true), (false, true), (false, true), (false, false),
+        ]);
+        assert_eq!(kinds("a##b...c"), vec![
+            TokenKind::Ident("a".to_string()), punct("##"), TokenKind::Ident("b".to_string()),
+            punct("..."), TokenKind::Ident("c".to_string()), TokenKind::Eof,
+        ]);
+    }
+
+    #[test]
+    fn display_token() {
+        let shown: Vec<String> = kinds(r#"x + 1u 2l 3ul "a\"\n\1" '\''"#).iter().map(|k| k.to_string()).collect();
+        assert_eq!(shown, vec!["x", "+", "1u", "2l", "3ul", r#""a\"\n\001""#, r"'\''", ""]);
+    }
+
+    #[test]
+    fn tokenize_literal() {
+        assert_eq!(kinds(r#""abc" "" 'a' '\n'"#), vec![
+            TokenKind::Str(b"abc".to_vec()), TokenKind::Str(Vec::new()),
+            TokenKind::Char(b'a'), TokenKind::Char(b'\n'), TokenKind::Eof,
+        ]);
+        assert_eq!(kinds(r#""\a\b\t\n\v\f\r\e\\\"\'\j\?""#), vec![
+            TokenKind::Str(vec![7, 8, 9, 10, 11, 12, 13, 27, b'\\', b'"', b'\'', b'j', b'?']),
+            TokenKind::Eof,
+        ]);
+        // octal takes at most 3 digits, hexadecimal takes all digits
+        assert_eq!(kinds(r#""\0\101\1500\x41\x00ff\xA5g""#), vec![
+            TokenKind::Str(vec![0, 65, 104, b'0', 65, 255, 165, b'g']),
+            TokenKind::Eof,
+        ]);
+        assert_eq!(kinds("\"あ\""), vec![TokenKind::Str("あ".as_bytes().to_vec()), TokenKind::Eof]);
+        assert_eq!(kinds(r"'\'' '\0' '\xff'"), vec![
+            TokenKind::Char(b'\''), TokenKind::Char(0), TokenKind::Char(255), TokenKind::Eof,
+        ]);
+
+        let tokens = Lexer::new(r#"1 "a\"b" 2"#).tokenize().unwrap();
+        assert_eq!(tokens[1].span(), Span::new(2, 8));
+    }
+
+    #[test]
+    fn tokenize_number() {
+        let n = |n: u64, ty: Type| TokenKind::Num(n, ty);
+        assert_eq!(kinds("0x1F 0XaB 017 0 0b101 0B0"), vec![
+            n(31, Type::Int), n(171, Type::Int), n(15, Type::Int), n(0, Type::Int), n(5, Type::Int), n(0, Type::Int),
+            TokenKind::Eof,
+        ]);
+        assert_eq!(kinds("1u 1L 1ll 1UL 1lu 1uLL 1LLU"), vec![
+            n(1, Type::UInt), n(1, Type::Long), n(1, Type::Long), n(1, Type::ULong), n(1, Type::ULong),
+            n(1, Type::ULong), n(1, Type::ULong), TokenKind::Eof,
+        ]);
+        // decimal literals are never unsigned without `u`
+        assert_eq!(kinds("2147483647 2147483648 4294967296 9223372036854775807"), vec![
+            n(i32::MAX as u64, Type::Int), n(1 << 31, Type::Long), n(1 << 32, Type::Long),
+            n(i64::MAX as u64, Type::Long), TokenKind::Eof,
+        ]);
+        assert_eq!(kinds("0x7fffffff 0x80000000 0x100000000 0x8000000000000000 0xffffffffffffffff"), vec![
+            n(i32::MAX as u64, Type::Int), n(1 << 31, Type::UInt), n(1 << 32, Type::Long),
+            n(1 << 63, Type::ULong), n(u64::MAX, Type::ULong), TokenKind::Eof,
+        ]);
+        assert_eq!(kinds("4294967295u 4294967296u 0x80000000l 0x8000000000000000L 9223372036854775808u"), vec![
+            n(u32::MAX as u64, Type::UInt), n(1 << 32, Type::ULong), n(1 << 31, Type::Long),
+            n(1 << 63, Type::ULong), n(1 << 63, Type::ULong), TokenKind::Eof,
+        ]);
+    }
+
+    #[test]
+    fn tokenize_literal_error() {
+        let error = |s: &str| {
+            let err = Lexer::new(s).tokenize().unwrap_err();
+            (err.location().unwrap().column, err.message().to_string())
+        };
+        assert_eq!(error(r#"1 + "abc"#), (5, "unclosed string literal".to_string()));
+        assert_eq!(error(r#""abc\"#), (1, "unclosed string literal".to_string()));
+        assert_eq!(error(r#""\xg""#), (3, "invalid hex escape sequence".to_string()));
+        assert_eq!(error("'ab'"), (1, "unclosed char literal".to_string()));
+        assert_eq!(error("'a"), (1, "unclosed char literal".to_string()));
+        assert_eq!(error("''"), (1, "empty char literal".to_string()));
+        assert_eq!(error("'あ'"), (1, "multibyte char literal is not supported".to_string()));
+        assert_eq!(error("1 + 09"), (5, "invalid integer literal".to_string()));
+        assert_eq!(error("0x"), (1, "invalid integer literal".to_string()));
+        assert_eq!(error("0b12"), (1, "invalid integer literal".to_string()));
+        assert_eq!(error("12ab"), (1, "invalid integer literal".to_string()));
+        assert_eq!(error("1lL 1uu"), (1, "invalid integer literal".to_string()));
+        assert_eq!(error("1 18446744073709551616"), (3, "integer literal is too large".to_string()));
+        assert_eq!(error("0x10000000000000000"), (1, "integer literal is too large".to_string()));
+        assert_eq!(error("9223372036854775808"), (1, "integer literal is too large".to_string()));
+    }
+
+    #[test]
+    fn tokenize_invalid() {
+        let err = Lexer::new("1 $ 2").tokenize().unwrap_err();
+        assert_eq!(err.message(), "invalid token");
+        assert_eq!(err.location().unwrap().column, 3);
+    }
+}
@@ -0,0 +1,134 @@
+pub mod utils;
+pub mod error;
+pub mod lexer;
+pub mod parser;
+pub mod node;
+pub mod sema;
+pub mod types;
+pub mod codegenerator;
+pub mod source;
+pub mod preprocessor;
+
+use crate::codegenerator::CodeGenerator;
+use crate::error::CompileError;
+use crate::lexer::Token;
+use crate::node::Program;
+use crate::parser::Parser;
+use crate::preprocessor::Preprocessor;
+use crate::sema::Sema;
+use crate::source::SourceMap;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    // name of the source file shown in error messages
+    pub file_name: Option<String>,
+    // directories searched for included files
+    pub include_paths: Vec<PathBuf>,
+}
+
+// Return x86-64 assembly (intel syntax) of the C program `source`.
+pub fn compile(source: &str, opts: &Options) -> Result<String, CompileError> {
+    let mut asm = String::new();
+    compile_to_fmt(source, opts, &mut asm)?;
+    Ok(asm)
+}
+
+// Write the assembly of `source` to a `fmt::Write` sink.
+// Nothing is written if the program has an error.
+pub fn compile_to_fmt<W: fmt::Write>(source: &str, opts: &Options, output: W) -> Result<(), CompileError> {
+    let program = check(source, opts).map_err(|e| in_file(e, opts))?;
+    CodeGenerator::new(output).generate(&program).map_err(|e| in_file(e, opts))
+}
+
+// Write the assembly of `source` to an `io::Write` sink.
+pub fn compile_to_io<W: io::Write>(source: &str, opts: &Options, output: W) -> Result<(), CompileError> {
+    let mut adapter = IoAdapter {output, error: None};
+    let result = compile_to_fmt(source, opts, &mut adapter);
+    match adapter.error {
+        Some(e) => Err(in_file(CompileError::new(format!("cannot write the assembly: {}", e)), opts)),
+        None => result,
+    }
+}
+
+// Return `source` preprocessed, as C source text with line markers.
+pub fn preprocess(source: &str, opts: &Options) -> Result<String, CompileError> {
+    let mut sources = SourceMap::new();
+    let tokens = preprocess_tokens(&mut sources, source, opts).map_err(|e| in_file(e, opts))?;
+    Ok(preprocessor::to_text(&sources, &tokens))
+}
+
+// Preprocess, parse and check `source`, returning the program ready for code generation.
+pub fn check(source: &str, opts: &Options) -> Result<Program, CompileError> {
+    let mut sources = SourceMap::new();
+    let tokens = preprocess_tokens(&mut sources, source, opts)?;
+    let program = Parser::new(&sources, tokens).parse()?;
+    Sema::new(&sources).check(program)
+}
+
+fn preprocess_tokens(sources: &mut SourceMap, source: &str, opts: &Options) -> Result<Vec<Token>, CompileError> {
+    let file = sources.add(opts.file_name.clone(), source.to_string());
+    Preprocessor::new(sources, opts.include_paths.clone()).preprocess(file)
+}
+
+// errors in included files already have their file names
+fn in_file(err: CompileError, opts: &Options) -> CompileError {
+    match &opts.file_name {
+        Some(name) if err.file_name().is_none() => err.in_file(name),
+        _ => err,
+    }
+}
+
+// `fmt::Write` over `io::Write`, keeping the I/O error which `fmt::Error` cannot carry
+struct IoAdapter<W> {
+    output: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoAdapter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.output.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let asm = compile("int main() { return 42; }", &Options::default()).unwrap();
+        assert!(asm.starts_with(".intel_syntax noprefix\n"));
+        assert!(asm.contains("    push 42\n"));
+
+        let mut bytes = Vec::new();
+        compile_to_io("int main() { return 42; }", &Options::default(), &mut bytes).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), asm);
+    }
+
+    #[test]
+    fn test_preprocess() {
+        let opts = Options {file_name: Some("foo.c".to_string()), ..Options::default()};
+        let text = preprocess("#define N 42\nint main() { return N; }", &opts).unwrap();
+        assert_eq!(text, "# 2 \"foo.c\"\nint main() { return 42; }\n");
+        let err = preprocess("#if 1", &opts).unwrap_err();
+        assert_eq!(err.to_string(), "foo.c:1: #if 1\n         ^ unterminated conditional directive");
+    }
+
+    #[test]
+    fn test_error() {
+        let opts = Options {file_name: Some("foo.c".to_string()), ..Options::default()};
+        let err = compile("int main() { return x; }", &opts).unwrap_err();
+        assert_eq!(err.to_string(), "foo.c:1: int main() { return x; }\n                             ^ undeclared variable `x`");
+
+        // I/O errors are reported as they are
+        let mut full = [0u8; 8];
+        let err = compile_to_io("int main() { return 0; }", &opts, &mut full[..]).unwrap_err();
+        assert_eq!(err.to_string(), "foo.c: error: cannot write the assembly: failed to write whole buffer");
+    }
+}
@@ -1,27 +1,101 @@
-pub mod utils;
-pub mod lexer;
-pub mod node;
-pub mod codegenerator;
-
-use crate::codegenerator::CodeGenerator;
+use compiler_v1::{compile, preprocess, Options};
 use std::env;
-// use anyhow::{anyhow, Result};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
 
+const USAGE: &str = "usage: compiler-v1 [-E] [-o <output>] [-I <dir>]... [<file> | <program> | -]";
 
+struct Args {
+    // `None` means stdout
+    output: Option<String>,
+    // `None` or "-" means stdin
+    input: Option<String>,
+    include_paths: Vec<PathBuf>,
+    // `-E`: write the preprocessed source instead of the assembly
+    preprocess_only: bool,
+}
 
-fn main() {
-    // read command line arguments
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Invalid number of command line arguments");
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {output: None, input: None, include_paths: Vec::new(), preprocess_only: false};
+    let mut iter = env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match &arg as &str {
+            "-o" => {
+                match iter.next() {
+                    Some(path) => args.output = Some(path),
+                    None => return Err("`-o` requires an output path".to_string()),
+                }
+            },
+            "-E" => args.preprocess_only = true,
+            "-I" => {
+                match iter.next() {
+                    Some(dir) => args.include_paths.push(PathBuf::from(dir)),
+                    None => return Err("`-I` requires a directory".to_string()),
+                }
+            },
+            _ if arg.starts_with("-I") => args.include_paths.push(PathBuf::from(&arg[2..])),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            _ => {
+                if args.input.is_some() {
+                    return Err("too many inputs".to_string());
+                }
+                args.input = Some(arg);
+            }
+        }
     }
+    Ok(args)
+}
 
-    // compile
-    /*
-    let mut compiler = CodeGenerator::from_str(&args[1]);
-    compiler.compile();
-    */
+// Return (file name, source).
+// The input is read from stdin, from the file if it exists, or is the program itself.
+fn read_input(input: Option<&str>) -> Result<(Option<String>, String), String> {
+    match input {
+        None | Some("-") => {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source)
+                .map_err(|e| format!("cannot read stdin: {}", e))?;
+            Ok((Some("-".to_string()), source))
+        },
+        Some(path) if Path::new(path).is_file() => {
+            let source = fs::read_to_string(path)
+                .map_err(|e| format!("cannot open {}: {}", path, e))?;
+            Ok((Some(path.to_string()), source))
+        },
+        Some(program) => Ok((None, program.to_string())),
+    }
 }
 
+fn run() -> Result<(), String> {
+    // read command line arguments
+    let args = parse_args().map_err(|e| format!("{}\n{}", e, USAGE))?;
+    let (file_name, source) = read_input(args.input.as_deref())?;
 
+    // compile
+    let opts = Options {file_name, include_paths: args.include_paths};
+    let output = if args.preprocess_only {
+        preprocess(&source, &opts)
+    } else {
+        compile(&source, &opts)
+    };
+    let output = output.map_err(|e| e.to_string())?;
+
+    match &args.output {
+        Some(path) => fs::write(path, output).map_err(|e| format!("cannot write {}: {}", path, e)),
+        None => {
+            print!("{}", output);
+            Ok(())
+        },
+    }
+}
 
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -1,38 +1,302 @@
+use crate::lexer::Span;
+use crate::types::{Member, StructType, Type};
+use crate::utils::align_to;
 
-#[derive(Debug, PartialEq)]
-pub enum NodeKind {
-    Op(String),
-    Num(usize),
-    LVar(String),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    // `&&` and `||`, which evaluate the right operand only if needed
+    LogAnd,
+    LogOr,
+}
+
+impl BinOp {
+    // the operator in C
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+            BinOp::BitAnd => "&",
+            BinOp::BitOr => "|",
+            BinOp::BitXor => "^",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::LogAnd => "&&",
+            BinOp::LogOr => "||",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    // `-x`
+    Neg,
+    // `&x`
+    Addr,
+    // `*x`
+    Deref,
+    // `!x`
+    Not,
+    // `~x`
+    BitNot,
 }
 
-type Link = Option<Box<Node>>;
+#[derive(Debug, PartialEq)]
+pub enum ExprKind {
+    Num(i64),
+    // string literal, without the terminating NUL
+    Str(Vec<u8>),
+    // `var` is resolved from `name` by `Sema`
+    Var {
+        name: String,
+        var: Option<Var>,
+    },
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    // `lhs = rhs`
+    Assign(Box<Expr>, Box<Expr>),
+    // `lhs op= rhs`, rewritten with `Assign` by `Sema`. `++x` is `x += 1`.
+    AssignOp(BinOp, Box<Expr>, Box<Expr>),
+    // `x++` (`Add`) or `x--` (`Sub`), rewritten with `AssignOp` by `Sema`
+    PostIncDec(BinOp, Box<Expr>),
+    // `cond ? then : els`
+    Cond {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        els: Box<Expr>,
+    },
+    // `lhs, rhs`
+    Comma(Box<Expr>, Box<Expr>),
+    // `name(args...)`
+    FuncCall {
+        name: String,
+        args: Vec<Expr>,
+    },
+    // `sizeof expr`, replaced with the size by `Sema`
+    Sizeof(Box<Expr>),
+    // `va_start(ap, last)`, where `last` is not kept
+    VaStart(Box<Expr>),
+    // `va_arg(ap, ty)`, the next variable argument as `ty`
+    VaArg(Box<Expr>, Type),
+    // `base.name`, where `member` is resolved from `name` by `Sema`.
+    // `p->name` is `(*p).name`.
+    Member {
+        base: Box<Expr>,
+        name: String,
+        member: Option<Member>,
+    },
+    // `(ty) expr`, conversion to the type of the node.
+    // `Sema` also inserts it for implicit conversions.
+    Cast(Box<Expr>),
+}
 
-pub struct Node {
-    kind: NodeKind,
-    lhs: Link,
-    rhs: Link,
+#[derive(Debug, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+    // type of the value, assigned by `Sema`
+    pub ty: Option<Type>,
 }
 
-impl Node {
-    pub fn new(kind: NodeKind, lhs: Link, rhs: Link) -> Self {
-        Self {kind, lhs, rhs}
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self {kind, span, ty: None}
     }
 
-    pub fn link(node: Node) -> Link {
-        Some(Box::new(node))
+    pub fn with_type(kind: ExprKind, span: Span, ty: Type) -> Self {
+        Self {kind, span, ty: Some(ty)}
     }
 
-    pub fn kind(&self) -> &NodeKind {
-        &self.kind
+    // type of the value; `Sema` must have checked the expression
+    pub fn ty(&self) -> &Type {
+        self.ty.as_ref().expect("expression is not type-checked")
     }
 
-    pub fn lhs(&self) -> &Link {
-        &self.lhs
+    pub fn is_lvalue(&self) -> bool {
+        matches!(
+            self.kind,
+            ExprKind::Var {..} | ExprKind::Str(_) | ExprKind::Unary(UnaryOp::Deref, _) | ExprKind::Member {..}
+        )
     }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StmtKind {
+    // `expr;`
+    Expr(Expr),
+    // `return expr;`
+    Return(Expr),
+    // `if (cond) then else els`
+    If {
+        cond: Expr,
+        then: Box<Stmt>,
+        els: Option<Box<Stmt>>,
+    },
+    // `while (cond) body`
+    While {
+        cond: Expr,
+        body: Box<Stmt>,
+    },
+    // `for (init; cond; inc) body`
+    For {
+        init: Option<Box<Expr>>,
+        cond: Option<Box<Expr>>,
+        inc: Option<Box<Expr>>,
+        body: Box<Stmt>,
+    },
+    // `{ stmt* }`
+    Block(Vec<Stmt>),
+    // `int a = 1, *b;`
+    Decl(Vec<Declaration>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
 
-    pub fn rhs(&self) -> &Link {
-        &self.rhs
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self {kind, span}
     }
+}
+
+// Local variable, placed at `rbp - offset` in the stack frame.
+// `offset` is assigned by `Sema`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LVar {
+    pub name: String,
+    pub offset: usize,
+    pub ty: Type,
+}
+
+// Global variable, placed at the label `name`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GVar {
+    pub name: String,
+    pub ty: Type,
+}
+
+// Variable a name refers to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Var {
+    Local(LVar),
+    Global(GVar),
+}
+
+impl Var {
+    pub fn ty(&self) -> &Type {
+        match self {
+            Var::Local(var) => &var.ty,
+            Var::Global(var) => &var.ty,
+        }
+    }
+}
+
+// Declaration of a local variable or a parameter
+#[derive(Debug, PartialEq)]
+pub struct Declaration {
+    pub var: LVar,
+    pub init: Option<Expr>,
+    pub span: Span,
+}
+
+// Function definition `ret_ty name(params..., ...) { body }`
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub ret_ty: Type,
+    pub params: Vec<Declaration>,
+    // whether it takes variable arguments after `params`
+    pub variadic: bool,
+    pub body: Vec<Stmt>,
+    // all local variables including `params`, collected by `Sema`
+    pub locals: Vec<LVar>,
+    // offset of the register save area for `va_start`, allocated by `Sema` if `variadic`
+    pub va_area: Option<usize>,
+    pub span: Span,
+}
+
+impl Function {
+    // bytes of the stack frame for `locals`, aligned to 16 bytes
+    pub fn stack_size(&self) -> usize {
+        let size = self.locals.iter().map(|var| var.offset).max().unwrap_or(0);
+        align_to(size, 16)
+    }
+}
+
+// Function declaration `ret_ty name(params..., ...);` without the body
+#[derive(Debug, PartialEq)]
+pub struct FuncDecl {
+    pub name: String,
+    pub ret_ty: Type,
+    pub params: Vec<Declaration>,
+    // whether it takes variable arguments after `params`
+    pub variadic: bool,
+    pub span: Span,
+}
+
+// Initial value of a global variable
+#[derive(Debug, PartialEq)]
+pub enum Initializer {
+    Expr(Expr),
+    // `{ init, ... }`
+    List(Vec<Initializer>, Span),
+}
+
+// Symbol whose address is a constant
+#[derive(Debug, Clone, PartialEq)]
+pub enum Symbol {
+    Global(String),
+    // string literal, without the terminating NUL
+    Str(Vec<u8>),
+}
+
+// Piece of the initial data of a global variable
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+    Bytes(Vec<u8>),
+    // 8-byte address of the symbol plus the offset
+    Addr(Symbol, i64),
+}
+
+// Definition of a global variable `ty name = init;`
+#[derive(Debug, PartialEq)]
+pub struct GlobalDecl {
+    pub var: GVar,
+    pub init: Option<Initializer>,
+    // initial data computed from `init` by `Sema`; zero-filled if `None`
+    pub data: Option<Vec<Data>>,
+    pub span: Span,
+}
 
+// Program is a list of global variables, function definitions and function declarations
+#[derive(Debug, Default)]
+pub struct Program {
+    pub globals: Vec<GlobalDecl>,
+    pub functions: Vec<Function>,
+    pub declarations: Vec<FuncDecl>,
+    // all the struct types, which own their definitions
+    pub structs: Vec<StructType>,
 }
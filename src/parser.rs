@@ -0,0 +1,1339 @@
+use crate::error::CompileError;
+use crate::node::{
+    BinOp, Declaration, Expr, ExprKind, FuncDecl, Function, GVar, GlobalDecl, Initializer, LVar, Program, Stmt,
+    StmtKind, UnaryOp,
+};
+use crate::lexer::{Span, Token, TokenKind};
+use crate::source::SourceMap;
+use crate::types::{StructKind, StructType, Type};
+
+/*
+
+program = (declspec (function | global_vars))*
+function = declarator "(" (params ("," "...")?)? ")" ("{" compound_stmt | ";")
+global_vars = (declarator ("=" initializer)? ("," declarator ("=" initializer)?)*)? ";"
+initializer = "{" (initializer ("," initializer)* ","?)? "}"
+            | assign
+params = param ("," param)*
+param = declspec declarator
+declspec = ("char" | "short" | "int" | "long" | "signed" | "unsigned")+
+         | ("struct" | "union") struct_decl
+         | "va_list"
+struct_decl = ident? ("{" (declspec declarator ("," declarator)* ";")* "}")?
+declarator = "*"* ident type_suffix
+type_suffix = ("[" num "]" type_suffix)?
+type_name = declspec "*"* type_suffix
+compound_stmt = (declaration | stmt)* "}"
+declaration = declspec (declarator ("=" assign)? ("," declarator ("=" assign)?)*)? ";"
+stmt = expr ";"
+     | "return" expr ";"
+     | "if" "(" expr ")" stmt ("else" stmt)?
+     | "while" "(" expr ")" stmt
+     | "for" "(" expr? ";" expr? ";" expr? ")" stmt
+     | "{" compound_stmt
+expr = assign ("," expr)?
+assign = conditional (assign_op assign)?
+assign_op = "=" | "+=" | "-=" | "*=" | "/=" | "%=" | "&=" | "|=" | "^=" | "<<=" | ">>="
+conditional = binary ("?" expr ":" conditional)?
+binary = cast (binary_op cast)*, by precedence climbing over BINARY_OPS
+cast = "(" type_name ")" cast
+     | unary
+unary = ("+" | "-" | "*" | "&" | "!" | "~") cast
+      | ("++" | "--") unary
+      | "sizeof" "(" type_name ")"
+      | "sizeof" unary
+      | "_Alignof" "(" type_name ")"
+      | postfix
+postfix = primary ("[" expr "]" | "." ident | "->" ident | "++" | "--")*
+primary = num
+        | char
+        | str
+        | ident ("(" args? ")")?
+        | va_builtin
+        | "(" expr ")"
+args = assign ("," assign)*
+va_builtin = "va_start" "(" assign "," ident ")"
+           | "va_arg" "(" assign "," type_name ")"
+           | "va_end" "(" assign ")"
+           | "va_copy" "(" assign "," assign ")"
+
+*/
+
+// Parameters are passed in registers, so their number is limited in function definitions.
+// See System V ABI.
+pub const MAX_ARGS: usize = 6;
+
+// binary operators as (punctuator, operator, precedence, whether operands are swapped),
+// where a larger precedence binds tighter
+const BINARY_OPS: [(&str, BinOp, usize, bool); 18] = [
+    ("||", BinOp::LogOr, 1, false),
+    ("&&", BinOp::LogAnd, 2, false),
+    ("|", BinOp::BitOr, 3, false),
+    ("^", BinOp::BitXor, 4, false),
+    ("&", BinOp::BitAnd, 5, false),
+    ("==", BinOp::Eq, 6, false),
+    ("!=", BinOp::Ne, 6, false),
+    ("<", BinOp::Lt, 7, false),
+    ("<=", BinOp::Le, 7, false),
+    (">", BinOp::Lt, 7, true),
+    (">=", BinOp::Le, 7, true),
+    ("<<", BinOp::Shl, 8, false),
+    (">>", BinOp::Shr, 8, false),
+    ("+", BinOp::Add, 9, false),
+    ("-", BinOp::Sub, 9, false),
+    ("*", BinOp::Mul, 10, false),
+    ("/", BinOp::Div, 10, false),
+    ("%", BinOp::Mod, 10, false),
+];
+
+// compound assignment operators
+const ASSIGN_OPS: [(&str, BinOp); 10] = [
+    ("+=", BinOp::Add),
+    ("-=", BinOp::Sub),
+    ("*=", BinOp::Mul),
+    ("/=", BinOp::Div),
+    ("%=", BinOp::Mod),
+    ("&=", BinOp::BitAnd),
+    ("|=", BinOp::BitOr),
+    ("^=", BinOp::BitXor),
+    ("<<=", BinOp::Shl),
+    (">>=", BinOp::Shr),
+];
+
+// keywords making up a basic type, in any order like `long unsigned int`
+const TYPE_KEYWORDS: [&str; 6] = ["char", "short", "int", "long", "signed", "unsigned"];
+
+type ParseResult<T> = Result<T, CompileError>;
+
+// Parser makes syntax tree from tokens.
+// Names are resolved and types are checked later by `Sema`,
+// except struct tags, which make up types.
+pub struct Parser<'a> {
+    sources: &'a SourceMap,
+    tokens: Vec<Token>,
+    pos: usize,
+    // struct and union tags, the innermost block scope last
+    tag_scopes: Vec<Vec<(String, StructType)>>,
+    // all the struct types made, to be owned by `Program::structs`
+    structs: Vec<StructType>,
+}
+
+impl<'a> Parser<'a> {
+    // `tokens` must end with `TokenKind::Eof`, as `Lexer::tokenize` returns.
+    // `sources` are the files `tokens` came from, used for error messages.
+    pub fn new(sources: &'a SourceMap, tokens: Vec<Token>) -> Self {
+        Self {sources, tokens, pos: 0, tag_scopes: vec![Vec::new()], structs: Vec::new()}
+    }
+
+    pub fn parse(&mut self) -> ParseResult<Program> {
+        self.program()
+    }
+
+    // Parse the tokens as one conditional expression, like in `#if`.
+    pub fn parse_const_expr(&mut self) -> ParseResult<Expr> {
+        let expr = self.conditional()?;
+        if !self.at_eof() {
+            return Err(self.error("extra token"));
+        }
+        Ok(expr)
+    }
+
+    // error at the next token
+    fn error(&self, message: &str) -> CompileError {
+        self.error_at(self.peek().span(), message)
+    }
+
+    fn error_at(&self, span: Span, message: &str) -> CompileError {
+        self.sources.error_at(span, message)
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> &Token {
+        let token = &self.tokens[self.pos];
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    // span from `start` to the end of the last consumed token
+    fn span_from(&self, start: Span) -> Span {
+        let end = if self.pos > 0 { self.tokens[self.pos - 1].span() } else { start };
+        start.to(end)
+    }
+
+    fn at_eof(&self) -> bool {
+        *self.peek().kind() == TokenKind::Eof
+    }
+
+    // consume the next token if it is `s`
+    fn consume(&mut self, s: &str) -> bool {
+        if self.peek().is(s) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> ParseResult<()> {
+        if self.consume(s) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", s)))
+        }
+    }
+
+    // whether `token` starts a declaration
+    fn is_typename(token: &Token) -> bool {
+        TYPE_KEYWORDS.iter().any(|keyword| token.is(keyword))
+            || token.is("struct") || token.is("union") || token.is("va_list")
+    }
+
+    fn expect_ident(&mut self) -> ParseResult<String> {
+        match self.peek().kind().clone() {
+            TokenKind::Ident(ident) => {
+                self.next();
+                Ok(ident)
+            },
+            _ => Err(self.error("expected an identifier")),
+        }
+    }
+
+    fn new_binary(op: BinOp, lhs: Expr, rhs: Expr) -> Expr {
+        let span = lhs.span.to(rhs.span);
+        Expr::new(ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), span)
+    }
+
+    // program = (declspec (function | global_vars))*
+    fn program(&mut self) -> ParseResult<Program> {
+        let mut program = Program::default();
+        while !self.at_eof() {
+            let start = self.peek().span();
+            let base = self.declspec()?;
+            if self.is_function(&base) {
+                self.function(base, start, &mut program)?;
+            } else {
+                program.globals.extend(self.global_vars(base)?);
+            }
+        }
+        program.structs = std::mem::take(&mut self.structs);
+        Ok(program)
+    }
+
+    // whether the next declarator is followed by `(`
+    fn is_function(&mut self, base: &Type) -> bool {
+        let pos = self.pos;
+        let is_function = self.declarator(base.clone()).is_ok() && self.peek().is("(");
+        self.pos = pos;
+        is_function
+    }
+
+    // global_vars = (declarator ("=" initializer)? ("," declarator ("=" initializer)?)*)? ";"
+    fn global_vars(&mut self, base: Type) -> ParseResult<Vec<GlobalDecl>> {
+        let mut globals = Vec::new();
+        while !self.consume(";") {
+            if !globals.is_empty() {
+                self.expect(",")?;
+            }
+            let (name, ty, span) = self.declarator(base.clone())?;
+            let init = if self.consume("=") { Some(self.initializer()?) } else { None };
+            globals.push(GlobalDecl {
+                var: GVar {name, ty},
+                init,
+                data: None,
+                span: self.span_from(span),
+            });
+        }
+        Ok(globals)
+    }
+
+    // initializer = "{" (initializer ("," initializer)* ","?)? "}"
+    //             | assign
+    fn initializer(&mut self) -> ParseResult<Initializer> {
+        let start = self.peek().span();
+        if !self.consume("{") {
+            return Ok(Initializer::Expr(self.assign()?));
+        }
+        let mut items = Vec::new();
+        while !self.consume("}") {
+            if !items.is_empty() {
+                self.expect(",")?;
+                // trailing comma
+                if self.consume("}") {
+                    break;
+                }
+            }
+            items.push(self.initializer()?);
+        }
+        Ok(Initializer::List(items, self.span_from(start)))
+    }
+
+    // function = declarator "(" (params ("," "...")?)? ")" ("{" compound_stmt | ";")
+    // params = param ("," param)*
+    // param = declspec declarator
+    // `start` is the span of the declspec `base`.
+    // The definition or the declaration is added to `program`.
+    fn function(&mut self, base: Type, start: Span, program: &mut Program) -> ParseResult<()> {
+        let (name, ret_ty, _) = self.declarator(base)?;
+        self.expect("(")?;
+        let mut params: Vec<Declaration> = Vec::new();
+        let mut variadic = false;
+        while !self.consume(")") {
+            if !params.is_empty() {
+                self.expect(",")?;
+                if self.consume("...") {
+                    variadic = true;
+                    self.expect(")")?;
+                    break;
+                }
+            }
+            let start = self.peek().span();
+            let base = self.declspec()?;
+            let (name, mut ty, _) = self.declarator(base)?;
+            // array parameters are pointers to the first elements
+            if let Type::Array(base, _) = ty {
+                ty = Type::pointer_to(*base);
+            }
+            params.push(Declaration {
+                var: LVar {name, offset: 0, ty},
+                init: None,
+                span: self.span_from(start),
+            });
+        }
+        if self.consume(";") {
+            program.declarations.push(FuncDecl {name, ret_ty, params, variadic, span: self.span_from(start)});
+            return Ok(());
+        }
+        if let Some(param) = params.get(MAX_ARGS) {
+            return Err(self.error_at(param.span, "too many parameters"));
+        }
+        self.expect("{")?;
+        let body = self.compound_stmt()?;
+
+        program.functions.push(Function {
+            name,
+            ret_ty,
+            params,
+            variadic,
+            body,
+            locals: Vec::new(),
+            va_area: None,
+            span: self.span_from(start),
+        });
+        Ok(())
+    }
+
+    // declspec = ("char" | "short" | "int" | "long" | "signed" | "unsigned")+
+    //          | ("struct" | "union") struct_decl
+    //          | "va_list"
+    // `long long` is the same as `long`.
+    fn declspec(&mut self) -> ParseResult<Type> {
+        let start = self.peek().span();
+        if self.consume("va_list") {
+            return Ok(Type::va_list());
+        }
+        if self.consume("struct") {
+            return self.struct_decl(StructKind::Struct);
+        }
+        if self.consume("union") {
+            return self.struct_decl(StructKind::Union);
+        }
+        if !Self::is_typename(self.peek()) {
+            return Err(self.error("expected a type name"));
+        }
+        // occurrences of each of `TYPE_KEYWORDS`
+        let mut counts = [0; TYPE_KEYWORDS.len()];
+        while let Some(i) = TYPE_KEYWORDS.iter().position(|keyword| self.peek().is(keyword)) {
+            self.next();
+            counts[i] += 1;
+        }
+        let [char, short, int, long, signed, unsigned] = counts;
+        let ty = match (char, short, int, long) {
+            _ if signed + unsigned > 1 => None,
+            (1, 0, 0, 0) => Some(Type::Char),
+            (0, 1, 0..=1, 0) => Some(Type::Short),
+            (0, 0, 0..=1, 0) => Some(Type::Int),
+            (0, 0, 0..=1, 1..=2) => Some(Type::Long),
+            _ => None,
+        };
+        match ty {
+            Some(ty) if unsigned == 1 => Ok(ty.to_unsigned()),
+            Some(ty) => Ok(ty),
+            None => Err(self.error_at(start, "invalid type")),
+        }
+    }
+
+    // struct_decl = ident? ("{" (declspec declarator ("," declarator)* ";")* "}")?
+    // A tag without members refers to the visible declaration of the tag,
+    // or declares an incomplete type if there is none.
+    fn struct_decl(&mut self, kind: StructKind) -> ParseResult<Type> {
+        let start = self.peek().span();
+        let tag = match self.peek().kind().clone() {
+            TokenKind::Ident(tag) => {
+                self.next();
+                Some(tag)
+            },
+            _ => None,
+        };
+
+        if !self.peek().is("{") {
+            let tag = match tag {
+                Some(tag) => tag,
+                None => return Err(self.error("expected `{`")),
+            };
+            let ty = match self.find_tag(&tag, false) {
+                Some(ty) if ty.kind() != kind => {
+                    return Err(self.error_at(start, &format!("wrong kind of tag `{}`", tag)));
+                },
+                Some(ty) => ty,
+                None => self.declare_tag(kind, tag),
+            };
+            return Ok(Type::Struct(ty));
+        }
+
+        // the tag is visible in the members, as an incomplete type
+        let ty = match tag {
+            Some(tag) => match self.find_tag(&tag, true) {
+                Some(ty) if ty.kind() != kind => {
+                    return Err(self.error_at(start, &format!("wrong kind of tag `{}`", tag)));
+                },
+                Some(ty) if ty.is_complete() => {
+                    return Err(self.error_at(start, &format!("redefinition of `{}`", tag)));
+                },
+                Some(ty) => ty,
+                None => self.declare_tag(kind, tag),
+            },
+            None => self.new_struct(kind, None),
+        };
+
+        self.expect("{")?;
+        let mut members: Vec<(String, Type)> = Vec::new();
+        while !self.consume("}") {
+            let base = self.declspec()?;
+            let mut first = true;
+            while !self.consume(";") {
+                if !first {
+                    self.expect(",")?;
+                }
+                first = false;
+                let (name, ty, span) = self.declarator(base.clone())?;
+                if ty.is_incomplete() {
+                    return Err(self.error_at(span, &format!("member `{}` has an incomplete type", name)));
+                }
+                if members.iter().any(|(declared, _)| *declared == name) {
+                    return Err(self.error_at(span, &format!("duplicate member `{}`", name)));
+                }
+                members.push((name, ty));
+            }
+        }
+        ty.define(members);
+        // the same limit as arrays, see `type_suffix`
+        if ty.size() > i32::MAX as usize {
+            return Err(self.error_at(start, "struct is too large"));
+        }
+        Ok(Type::Struct(ty))
+    }
+
+    // the type declared with `tag`, only in the innermost scope if `innermost`
+    fn find_tag(&self, tag: &str, innermost: bool) -> Option<StructType> {
+        let scopes = if innermost { &self.tag_scopes[self.tag_scopes.len() - 1..] } else { &self.tag_scopes[..] };
+        scopes.iter().rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(declared, _)| declared == tag)
+            .map(|(_, ty)| ty.clone())
+    }
+
+    // declare an incomplete type of `tag` in the innermost scope
+    fn declare_tag(&mut self, kind: StructKind, tag: String) -> StructType {
+        let ty = self.new_struct(kind, Some(tag.clone()));
+        self.tag_scopes.last_mut().unwrap().push((tag, ty.clone()));
+        ty
+    }
+
+    fn new_struct(&mut self, kind: StructKind, tag: Option<String>) -> StructType {
+        let ty = StructType::new(kind, tag);
+        self.structs.push(ty.clone());
+        ty
+    }
+
+    // declarator = "*"* ident type_suffix
+    // Return the declared name, its type and the span of the declarator.
+    fn declarator(&mut self, mut ty: Type) -> ParseResult<(String, Type, Span)> {
+        let start = self.peek().span();
+        while self.consume("*") {
+            ty = Type::pointer_to(ty);
+        }
+        let name = self.expect_ident()?;
+        let ty = self.type_suffix(ty)?;
+        Ok((name, ty, self.span_from(start)))
+    }
+
+    // type_suffix = ("[" num "]" type_suffix)?
+    // `int a[2][3]` is an array of 2 arrays of 3 ints.
+    fn type_suffix(&mut self, ty: Type) -> ParseResult<Type> {
+        if !self.consume("[") {
+            return Ok(ty);
+        }
+        let len_token = self.peek().clone();
+        let len = match *len_token.kind() {
+            TokenKind::Num(len, _) => len,
+            _ => return Err(self.error("expected an array length")),
+        };
+        self.next();
+        self.expect("]")?;
+        let ty = self.type_suffix(ty)?;
+        // the size must fit in 32-bit displacements of the stack offsets
+        match (ty.size() as u64).checked_mul(len) {
+            Some(size) if size <= i32::MAX as u64 => Ok(Type::array_of(ty, len as usize)),
+            _ => Err(self.error_at(len_token.span(), "array is too large")),
+        }
+    }
+
+    // type_name = declspec "*"* type_suffix
+    fn type_name(&mut self) -> ParseResult<Type> {
+        let mut ty = self.declspec()?;
+        while self.consume("*") {
+            ty = Type::pointer_to(ty);
+        }
+        self.type_suffix(ty)
+    }
+
+    // compound_stmt = (declaration | stmt)* "}"
+    fn compound_stmt(&mut self) -> ParseResult<Vec<Stmt>> {
+        self.tag_scopes.push(Vec::new());
+        let mut body = Vec::new();
+        while !self.consume("}") {
+            if self.at_eof() {
+                return Err(self.error("expected `}`"));
+            }
+            if Self::is_typename(self.peek()) {
+                body.push(self.declaration()?);
+            } else {
+                body.push(self.stmt()?);
+            }
+        }
+        self.tag_scopes.pop();
+        Ok(body)
+    }
+
+    // declaration = declspec (declarator ("=" assign)? ("," declarator ("=" assign)?)*)? ";"
+    fn declaration(&mut self) -> ParseResult<Stmt> {
+        let start = self.peek().span();
+        let base = self.declspec()?;
+        let mut decls = Vec::new();
+        while !self.consume(";") {
+            if !decls.is_empty() {
+                self.expect(",")?;
+            }
+            let (name, ty, span) = self.declarator(base.clone())?;
+            let init = if self.consume("=") { Some(self.assign()?) } else { None };
+            decls.push(Declaration {
+                var: LVar {name, offset: 0, ty},
+                init,
+                span: self.span_from(span),
+            });
+        }
+        Ok(Stmt::new(StmtKind::Decl(decls), self.span_from(start)))
+    }
+
+    // stmt = expr ";"
+    //      | "return" expr ";"
+    //      | "if" "(" expr ")" stmt ("else" stmt)?
+    //      | "while" "(" expr ")" stmt
+    //      | "for" "(" expr? ";" expr? ";" expr? ")" stmt
+    //      | "{" compound_stmt
+    fn stmt(&mut self) -> ParseResult<Stmt> {
+        let start = self.peek().span();
+        let kind = self.stmt_kind()?;
+        Ok(Stmt::new(kind, self.span_from(start)))
+    }
+
+    fn stmt_kind(&mut self) -> ParseResult<StmtKind> {
+        if self.consume("return") {
+            let expr = self.expr()?;
+            self.expect(";")?;
+            return Ok(StmtKind::Return(expr));
+        }
+
+        if self.consume("if") {
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            let then = Box::new(self.stmt()?);
+            let els = if self.consume("else") {
+                Some(Box::new(self.stmt()?))
+            } else {
+                None
+            };
+            return Ok(StmtKind::If {cond, then, els});
+        }
+
+        if self.consume("while") {
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            let body = Box::new(self.stmt()?);
+            return Ok(StmtKind::While {cond, body});
+        }
+
+        if self.consume("for") {
+            self.expect("(")?;
+            let init = self.expr_until(";")?;
+            let cond = self.expr_until(";")?;
+            let inc = self.expr_until(")")?;
+            let body = Box::new(self.stmt()?);
+            return Ok(StmtKind::For {init, cond, inc, body});
+        }
+
+        if self.consume("{") {
+            return Ok(StmtKind::Block(self.compound_stmt()?));
+        }
+
+        let expr = self.expr()?;
+        self.expect(";")?;
+        Ok(StmtKind::Expr(expr))
+    }
+
+    // expr? followed by `end`
+    fn expr_until(&mut self, end: &str) -> ParseResult<Option<Box<Expr>>> {
+        if self.consume(end) {
+            return Ok(None);
+        }
+        let expr = self.expr()?;
+        self.expect(end)?;
+        Ok(Some(Box::new(expr)))
+    }
+
+    // expr = assign ("," expr)?
+    fn expr(&mut self) -> ParseResult<Expr> {
+        let node = self.assign()?;
+        if self.consume(",") {
+            let rhs = self.expr()?;
+            let span = node.span.to(rhs.span);
+            return Ok(Expr::new(ExprKind::Comma(Box::new(node), Box::new(rhs)), span));
+        }
+        Ok(node)
+    }
+
+    // assign = conditional (assign_op assign)?
+    // assign_op = "=" | "+=" | "-=" | "*=" | "/=" | "%=" | "&=" | "|=" | "^=" | "<<=" | ">>="
+    fn assign(&mut self) -> ParseResult<Expr> {
+        let node = self.conditional()?;
+        let op = if self.consume("=") {
+            None
+        } else if let Some(&(_, op)) = ASSIGN_OPS.iter().find(|(punct, _)| self.peek().is(punct)) {
+            self.next();
+            Some(op)
+        } else {
+            return Ok(node);
+        };
+        if !node.is_lvalue() {
+            return Err(self.error_at(node.span, "not an lvalue"));
+        }
+        let rhs = self.assign()?;
+        let span = node.span.to(rhs.span);
+        let kind = match op {
+            None => ExprKind::Assign(Box::new(node), Box::new(rhs)),
+            Some(op) => ExprKind::AssignOp(op, Box::new(node), Box::new(rhs)),
+        };
+        Ok(Expr::new(kind, span))
+    }
+
+    // conditional = binary ("?" expr ":" conditional)?
+    fn conditional(&mut self) -> ParseResult<Expr> {
+        let cond = self.binary(1)?;
+        if !self.consume("?") {
+            return Ok(cond);
+        }
+        let then = self.expr()?;
+        self.expect(":")?;
+        let els = self.conditional()?;
+        let span = cond.span.to(els.span);
+        let kind = ExprKind::Cond {cond: Box::new(cond), then: Box::new(then), els: Box::new(els)};
+        Ok(Expr::new(kind, span))
+    }
+
+    // binary = cast (binary_op cast)*
+    // where binary operators of precedence `min_prec` or higher are parsed,
+    // left-associatively as in BINARY_OPS.
+    fn binary(&mut self, min_prec: usize) -> ParseResult<Expr> {
+        let mut node = self.cast()?;
+        loop {
+            let found = BINARY_OPS.iter().find(|(punct, ..)| self.peek().is(punct));
+            let &(_, op, prec, swap) = match found {
+                Some(entry) if entry.2 >= min_prec => entry,
+                _ => return Ok(node),
+            };
+            self.next();
+            let rhs = self.binary(prec + 1)?;
+            node = if swap {
+                // instead of A > B, implement B < A
+                let span = node.span.to(rhs.span);
+                Expr {span, ..Self::new_binary(op, rhs, node)}
+            } else {
+                Self::new_binary(op, node, rhs)
+            };
+        }
+    }
+
+    // cast = "(" type_name ")" cast
+    //      | unary
+    fn cast(&mut self) -> ParseResult<Expr> {
+        let start = self.peek().span();
+        if !(self.peek().is("(") && Self::is_typename(&self.tokens[self.pos + 1])) {
+            return self.unary();
+        }
+        self.next();
+        let ty = self.type_name()?;
+        self.expect(")")?;
+        let operand = self.cast()?;
+        let span = start.to(operand.span);
+        Ok(Expr::with_type(ExprKind::Cast(Box::new(operand)), span, ty))
+    }
+
+    // unary = ("+" | "-" | "*" | "&" | "!" | "~") cast
+    //       | ("++" | "--") unary
+    //       | "sizeof" "(" type_name ")"
+    //       | "sizeof" unary
+    //       | "_Alignof" "(" type_name ")"
+    //       | postfix
+    fn unary(&mut self) -> ParseResult<Expr> {
+        let start = self.peek().span();
+        if self.consume("+") {
+            return self.cast();
+        }
+
+        if self.consume("sizeof") {
+            if self.peek().is("(") && Self::is_typename(&self.tokens[self.pos + 1]) {
+                self.next();
+                let ty = self.type_name()?;
+                self.expect(")")?;
+                if ty.is_incomplete() {
+                    return Err(self.error_at(start, "incomplete type"));
+                }
+                // `size_t` is `unsigned long`
+                return Ok(Expr::with_type(ExprKind::Num(ty.size() as i64), self.span_from(start), Type::ULong));
+            }
+            let operand = self.unary()?;
+            let span = start.to(operand.span);
+            return Ok(Expr::new(ExprKind::Sizeof(Box::new(operand)), span));
+        }
+
+        if self.consume("_Alignof") {
+            self.expect("(")?;
+            let ty = self.type_name()?;
+            self.expect(")")?;
+            if ty.is_incomplete() {
+                return Err(self.error_at(start, "incomplete type"));
+            }
+            return Ok(Expr::with_type(ExprKind::Num(ty.align() as i64), self.span_from(start), Type::ULong));
+        }
+
+        // `++x` is `x += 1`
+        for &(punct, op) in &[("++", BinOp::Add), ("--", BinOp::Sub)] {
+            if self.consume(punct) {
+                let operand = self.unary()?;
+                if !operand.is_lvalue() {
+                    return Err(self.error_at(operand.span, "not an lvalue"));
+                }
+                let span = start.to(operand.span);
+                let one = Expr::new(ExprKind::Num(1), span);
+                return Ok(Expr::new(ExprKind::AssignOp(op, Box::new(operand), Box::new(one)), span));
+            }
+        }
+
+        let op = if self.consume("-") {
+            UnaryOp::Neg
+        } else if self.consume("*") {
+            UnaryOp::Deref
+        } else if self.consume("&") {
+            UnaryOp::Addr
+        } else if self.consume("!") {
+            UnaryOp::Not
+        } else if self.consume("~") {
+            UnaryOp::BitNot
+        } else {
+            return self.postfix();
+        };
+        let operand = self.cast()?;
+        if op == UnaryOp::Addr && !operand.is_lvalue() {
+            return Err(self.error_at(operand.span, "not an lvalue"));
+        }
+        let span = start.to(operand.span);
+        Ok(Expr::new(ExprKind::Unary(op, Box::new(operand)), span))
+    }
+
+    // postfix = primary ("[" expr "]" | "." ident | "->" ident | "++" | "--")*
+    fn postfix(&mut self) -> ParseResult<Expr> {
+        let mut node = self.primary()?;
+        loop {
+            if self.consume("[") {
+                // `a[i]` is `*(a + i)`
+                let index = self.expr()?;
+                self.expect("]")?;
+                let span = self.span_from(node.span);
+                let addr = Self::new_binary(BinOp::Add, node, index);
+                node = Expr::new(ExprKind::Unary(UnaryOp::Deref, Box::new(addr)), span);
+            } else if self.consume(".") {
+                let name = self.expect_ident()?;
+                let span = self.span_from(node.span);
+                node = Expr::new(ExprKind::Member {base: Box::new(node), name, member: None}, span);
+            } else if self.consume("->") {
+                let name = self.expect_ident()?;
+                let span = self.span_from(node.span);
+                let base = Expr::new(ExprKind::Unary(UnaryOp::Deref, Box::new(node)), span);
+                node = Expr::new(ExprKind::Member {base: Box::new(base), name, member: None}, span);
+            } else if self.peek().is("++") || self.peek().is("--") {
+                let op = if self.peek().is("++") { BinOp::Add } else { BinOp::Sub };
+                self.next();
+                if !node.is_lvalue() {
+                    return Err(self.error_at(node.span, "not an lvalue"));
+                }
+                let span = self.span_from(node.span);
+                node = Expr::new(ExprKind::PostIncDec(op, Box::new(node)), span);
+            } else {
+                return Ok(node);
+            }
+        }
+    }
+
+    // primary = num
+    //         | char
+    //         | str
+    //         | ident ("(" args? ")")?
+    //         | va_builtin
+    //         | "(" expr ")"
+    fn primary(&mut self) -> ParseResult<Expr> {
+        let start = self.peek().span();
+        if self.consume("(") {
+            let node = self.expr()?;
+            self.expect(")")?;
+            return Ok(Expr {span: self.span_from(start), ..node});
+        }
+
+        match self.peek().kind().clone() {
+            // the lexer gives the type of the number
+            TokenKind::Num(num, ty) => {
+                self.next();
+                Ok(Expr::with_type(ExprKind::Num(num as i64), start, ty))
+            },
+            // a char literal is an `int` of the value as a (signed) `char`
+            TokenKind::Char(c) => {
+                self.next();
+                Ok(Expr::new(ExprKind::Num(c as i8 as i64), start))
+            },
+            TokenKind::Str(bytes) => {
+                self.next();
+                Ok(Expr::new(ExprKind::Str(bytes), start))
+            },
+            TokenKind::Ident(ident) => {
+                self.next();
+                if self.consume("(") {
+                    if ["va_start", "va_arg", "va_end", "va_copy"].contains(&ident.as_str()) {
+                        return self.va_builtin(&ident, start);
+                    }
+                    return self.func_call(ident, start);
+                }
+                Ok(Expr::new(ExprKind::Var {name: ident, var: None}, start))
+            },
+            _ => Err(self.error("expected an expression")),
+        }
+    }
+
+    // args? ")"
+    // args = assign ("," assign)*
+    fn func_call(&mut self, name: String, start: Span) -> ParseResult<Expr> {
+        let mut args = Vec::new();
+        while !self.consume(")") {
+            if !args.is_empty() {
+                self.expect(",")?;
+            }
+            args.push(self.assign()?);
+        }
+        Ok(Expr::new(ExprKind::FuncCall {name, args}, self.span_from(start)))
+    }
+
+    // va_builtin = "va_start" "(" assign "," ident ")"
+    //            | "va_arg" "(" assign "," type_name ")"
+    //            | "va_end" "(" assign ")"
+    //            | "va_copy" "(" assign "," assign ")"
+    // `(` has been read. `va_end(ap)` does nothing, and `va_copy(dst, src)` is `*dst = *src`.
+    fn va_builtin(&mut self, name: &str, start: Span) -> ParseResult<Expr> {
+        let ap = Box::new(self.assign()?);
+        let kind = match name {
+            "va_start" => {
+                self.expect(",")?;
+                self.expect_ident()?;
+                ExprKind::VaStart(ap)
+            },
+            "va_arg" => {
+                self.expect(",")?;
+                ExprKind::VaArg(ap, self.type_name()?)
+            },
+            "va_copy" => {
+                self.expect(",")?;
+                let src = self.assign()?;
+                let (dst_span, src_span) = (ap.span, src.span);
+                let dst = Expr::new(ExprKind::Unary(UnaryOp::Deref, ap), dst_span);
+                let src = Expr::new(ExprKind::Unary(UnaryOp::Deref, Box::new(src)), src_span);
+                ExprKind::Assign(Box::new(dst), Box::new(src))
+            },
+            _ => ExprKind::Comma(ap, Box::new(Expr::new(ExprKind::Num(0), start))),
+        };
+        self.expect(")")?;
+        Ok(Expr::new(kind, self.span_from(start)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node() {
+        test_parse("1*(2+3);");
+        test_parse("1 + 20+ 4;");
+        test_parse(" 9- 6 * 10;");
+        test_parse("1-10/100 +1000 * 10000;");
+        test_parse("((2-20)*200 + 2000)*(21 - 201);");
+        test_parse("((100 + 100)* 10) + 100;");
+        test_parse("1 == 1;");
+        test_parse("1 != 1;");
+        test_parse("1 <= 1;");
+        test_parse("1 >= 1;");
+        test_parse("1 < 1;");
+        test_parse("1 > 1;");
+        test_parse("1 == 1 == 1;");
+        test_parse("1 > 1 > 1;");
+        test_parse("a = 1;");
+        test_parse("a = 1 + 3;");
+        test_parse("a = b * 3 - p;");
+    }
+
+    #[test]
+    fn test_tree() {
+        assert_eq!(sexp("1 + 2 * 3;"), "(+ 1 (* 2 3))");
+        assert_eq!(sexp("(1 + 2) * 3;"), "(* (+ 1 2) 3)");
+        assert_eq!(sexp("1 > 2;"), "(< 2 1)");
+        assert_eq!(sexp("-foo;"), "(neg foo)");
+        assert_eq!(sexp("+foo;"), "foo");
+        assert_eq!(sexp("- -1;"), "(neg (neg 1))");
+        assert_eq!(sexp("*&a = **b * 2;"), "(= (deref (addr a)) (* (deref (deref b)) 2))");
+        assert_eq!(sexp("a = b = 1 == 2;"), "(= a (= b (== 1 2)))");
+        assert_eq!(sexp("1; 2;"), "1 2");
+        assert_eq!(sexp("return a = 1; return 2;"), "(return (= a 1)) (return 2)");
+        assert_eq!(sexp("if (a) 1; else if (b) 2; else 3;"), "(if a 1 (if b 2 3))");
+        assert_eq!(sexp("if (a) if (b) 1; else 2;"), "(if a (if b 1 2))");
+        assert_eq!(sexp("while (i < 10) i = i + 1;"), "(while (< i 10) (= i (+ i 1)))");
+        assert_eq!(sexp("for (i = 0; i < 10; i = i + 1) a;"), "(for (= i 0) (< i 10) (= i (+ i 1)) a)");
+        assert_eq!(sexp("for (;;) return 1;"), "(for _ _ _ (return 1))");
+        assert_eq!(sexp("{1; {2;} {}} 3;"), "{1 {2} {}} 3");
+        assert_eq!(sexp("while (1) {a = 1; b = 2;}"), "(while 1 {(= a 1) (= b 2)})");
+        assert_eq!(sexp("foo(); bar(1, a = 2) + 3;"), "(foo) (+ (bar 1 (= a 2)) 3)");
+        assert_eq!(sexp("a[1] = *b[2];"), "(= (deref (+ a 1)) (deref (deref (+ b 2))))");
+        assert_eq!(sexp("a[1][i + 2];"), "(deref (+ (deref (+ a 1)) (+ i 2)))");
+        assert_eq!(sexp("sizeof a + 1; sizeof(a[0]); sizeof -a;"), "(+ (sizeof a) 1) (sizeof (deref (+ a 0))) (sizeof (neg a))");
+        assert_eq!(sexp("sizeof(int); sizeof(int *); sizeof(int[3][2]);"), "4 8 24");
+        assert_eq!(sexp("sizeof(char); sizeof(char[3]);"), "1 3");
+        assert_eq!(sexp(r#"'a'; '\xff'; "ab\n"[1];"#), r#"97 -1 (deref (+ "ab\n" 1))"#);
+        assert_eq!(sexp("(long)a; (char *)(int)-a * 2; (a) * 2; -(short)a;"),
+            "(cast Long a) (* (cast Ptr(Char) (cast Int (neg a))) 2) (* a 2) (neg (cast Short a))");
+        assert_eq!(sexp("sizeof(long); sizeof(unsigned short); sizeof(long long *);"), "8 2 8");
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(sexp("a || b && c | d ^ e & f == g < h << i + j * k;"),
+            "(|| a (&& b (| c (^ d (& e (== f (< g (<< h (+ i (* j k))))))))))");
+        assert_eq!(sexp("a * b + c << d < e == f & g ^ h | i && j || k;"),
+            "(|| (&& (| (^ (& (== (< (<< (+ (* a b) c) d) e) f) g) h) i) j) k)");
+        assert_eq!(sexp("a - b - c; a % b / c; a >> b << c; a >= b > c;"),
+            "(- (- a b) c) (/ (% a b) c) (<< (>> a b) c) (< c (<= b a))");
+        assert_eq!(sexp("!a; ~-a; !!a;"), "(not a) (bitnot (neg a)) (not (not a))");
+        assert_eq!(sexp("a ? b : c ? d : e; a || b ? c = 1 : d;"), "(? a b (? c d e)) (? (|| a b) (= c 1) d)");
+        assert_eq!(sexp("a, b = c, d; f((a, b), c);"), "(, a (, (= b c) d)) (f (, a b) c)");
+        assert_eq!(sexp("a += b -= 2; a <<= 1; a %= b | c;"), "(+= a (-= b 2)) (<<= a 1) (%= a (| b c))");
+        assert_eq!(sexp("++a; --*p; a++; p->x--; -a++;"),
+            "(+= a 1) (-= (deref p) 1) (post++ a) (post-- (. (deref p) x)) (neg (post++ a))");
+        assert_eq!(sexp("a+++b; a---b;"), "(+ (post++ a) b) (- (post-- a) b)");
+    }
+
+    #[test]
+    fn test_function() {
+        let program = parse("int add(int a, int *b) { return a + *b; } int *main() { return 0; }");
+        let names: Vec<&str> = program.functions.iter().map(|f| &f.name as &str).collect();
+        assert_eq!(names, vec!["add", "main"]);
+
+        let add = &program.functions[0];
+        let params: Vec<(&str, &Type)> = add.params.iter()
+            .map(|param| (&param.var.name as &str, &param.var.ty))
+            .collect();
+        assert_eq!(params, vec![("a", &Type::Int), ("b", &Type::pointer_to(Type::Int))]);
+        assert_eq!(add.ret_ty, Type::Int);
+        assert_eq!(program.functions[1].ret_ty, Type::pointer_to(Type::Int));
+
+        // array parameters are pointers
+        let program = parse("int f(int a[3], int *b[2][4]) {}");
+        let types: Vec<&Type> = program.functions[0].params.iter().map(|param| &param.var.ty).collect();
+        assert_eq!(types, vec![
+            &Type::pointer_to(Type::Int),
+            &Type::pointer_to(Type::array_of(Type::pointer_to(Type::Int), 4)),
+        ]);
+
+        // declarations have no body, and may take variable arguments
+        let program = parse("int printf(char *fmt, ...); char f(int a, int b, int c, int d, int e, int f, int g);");
+        assert!(program.functions.is_empty());
+        let decls: Vec<(&str, usize, bool)> = program.declarations.iter()
+            .map(|decl| (&decl.name as &str, decl.params.len(), decl.variadic))
+            .collect();
+        assert_eq!(decls, vec![("printf", 1, true), ("f", 7, false)]);
+        assert_eq!(program.declarations[1].ret_ty, Type::Char);
+
+        let program = parse("int f(int n, ...) { va_list ap; }");
+        assert!(program.functions[0].variadic);
+        assert_eq!(
+            sexp("va_list ap, aq; va_start(ap, n); va_arg(ap, char *); va_copy(aq, ap); va_end(ap);"),
+            "(decl ([1] ap) ([1] aq)) (va_start ap) (va_arg ap Ptr(Char)) (= (deref aq) (deref ap)) (, ap 0)",
+        );
+    }
+
+    #[test]
+    fn test_declaration() {
+        assert_eq!(sexp("int a; int *b = &a, **c, d = 1 + 2;"), "(decl a) (decl (* b) (addr a) (* (* c)) d (+ 1 2))");
+        assert_eq!(sexp("int; { int a; a; }"), "(decl) {(decl a) a}");
+        assert_eq!(sexp("int a[3], *b[2][4];"), "(decl ([3] a) (* ([4] ([2] b))))");
+        assert_eq!(sexp("char c, *s = \"\";"), "(decl c (* s) \"\")");
+        let main = parse_main("int x, *y;");
+        match &main.body[0].kind {
+            StmtKind::Decl(decls) => {
+                assert_eq!(decls[0].var.ty, Type::Int);
+                assert_eq!(decls[1].var.ty, Type::pointer_to(Type::Int));
+            },
+            _ => panic!("expected a declaration"),
+        }
+    }
+
+    #[test]
+    fn test_declspec() {
+        let types = |s: &str| -> Vec<Type> {
+            match parse_main(s).body.into_iter().next().map(|stmt| stmt.kind) {
+                Some(StmtKind::Decl(decls)) => decls.into_iter().map(|decl| decl.var.ty).collect(),
+                _ => panic!("expected a declaration"),
+            }
+        };
+        assert_eq!(types("short a;"), vec![Type::Short]);
+        assert_eq!(types("short int a;"), vec![Type::Short]);
+        assert_eq!(types("int short a;"), vec![Type::Short]);
+        assert_eq!(types("long a, *b;"), vec![Type::Long, Type::pointer_to(Type::Long)]);
+        assert_eq!(types("long int a;"), vec![Type::Long]);
+        assert_eq!(types("long long a;"), vec![Type::Long]);
+        assert_eq!(types("unsigned a;"), vec![Type::UInt]);
+        assert_eq!(types("signed a;"), vec![Type::Int]);
+        assert_eq!(types("unsigned char a;"), vec![Type::UChar]);
+        assert_eq!(types("signed char a;"), vec![Type::Char]);
+        assert_eq!(types("long unsigned int a;"), vec![Type::ULong]);
+        assert_eq!(types("unsigned long long a;"), vec![Type::ULong]);
+        assert_eq!(types("short unsigned a;"), vec![Type::UShort]);
+
+        let program = parse("unsigned long f(short a, unsigned char b) {}");
+        assert_eq!(program.functions[0].ret_ty, Type::ULong);
+        let types: Vec<&Type> = program.functions[0].params.iter().map(|param| &param.var.ty).collect();
+        assert_eq!(types, vec![&Type::Short, &Type::UChar]);
+    }
+
+    #[test]
+    fn test_struct() {
+        assert_eq!(sexp("a.x; p->y.z; a.b[1].c; &p->x;"),
+            "(. a x) (. (. (deref p) y) z) (. (deref (+ (. a b) 1)) c) (addr (. (deref p) x))");
+        assert_eq!(sexp("sizeof(struct {char a; int b;}); sizeof(union {char a; int b;}); sizeof(struct {});"), "8 4 0");
+        assert_eq!(sexp("_Alignof(char); _Alignof(long); _Alignof(struct {char a; short b;}[3]);"), "1 8 2");
+
+        let types = |s: &str| -> Vec<Type> {
+            parse_main(s).body.into_iter().flat_map(|stmt| match stmt.kind {
+                StmtKind::Decl(decls) => decls.into_iter().map(|decl| decl.var.ty).collect(),
+                _ => Vec::new(),
+            }).collect()
+        };
+        // tags refer to the same type
+        let tys = types("struct s {int a;} x; struct s y, *z;");
+        assert_eq!(tys[0], tys[1]);
+        assert_eq!(tys[2], Type::pointer_to(tys[0].clone()));
+        match &tys[0] {
+            Type::Struct(ty) => {
+                assert_eq!(ty.tag(), Some("s"));
+                assert_eq!(ty.members()[0].name, "a");
+            },
+            _ => panic!("expected a struct"),
+        }
+
+        // each definition makes a new type, and tags in inner scopes shadow outer ones
+        let tys = types("struct {int a;} x; struct {int a;} y;");
+        assert_ne!(tys[0], tys[1]);
+        assert_eq!(sexp("struct s {int a;} x; { struct s {char b;} y; sizeof(struct s); } sizeof(struct s);"),
+            "(decl x) {(decl y) 1} 4");
+
+        // a tag declared before its definition
+        let tys = types("struct node *p; struct node {int v; struct node *next;} n;");
+        assert_eq!(tys[0], Type::pointer_to(tys[1].clone()));
+        assert_eq!(tys[1].size(), 16);
+
+        // tags are also visible from functions
+        let program = parse("struct s {int a; int b;}; struct s g; int f(struct s *p) { struct s l; return sizeof(struct s); }");
+        assert_eq!(program.globals[0].var.ty.size(), 8);
+        assert_eq!(program.functions[0].params[0].var.ty, Type::pointer_to(program.globals[0].var.ty.clone()));
+    }
+
+    #[test]
+    fn test_global() {
+        let program = parse("int x, *p = &x; int f() {} char s[3] = {1, {2}, }; int y;");
+        let globals: Vec<(&str, &Type)> = program.globals.iter()
+            .map(|global| (&global.var.name as &str, &global.var.ty))
+            .collect();
+        assert_eq!(globals, vec![
+            ("x", &Type::Int),
+            ("p", &Type::pointer_to(Type::Int)),
+            ("s", &Type::array_of(Type::Char, 3)),
+            ("y", &Type::Int),
+        ]);
+        assert_eq!(program.functions.len(), 1);
+        assert!(program.globals[0].init.is_none());
+        match &program.globals[2].init {
+            Some(Initializer::List(items, _)) => {
+                assert_eq!(items.len(), 2);
+                assert!(matches!(&items[1], Initializer::List(inner, _) if inner.len() == 1));
+            },
+            _ => panic!("expected an initializer list"),
+        }
+    }
+
+    #[test]
+    fn test_span() {
+        let src = "int main() { int *p = &foo; foo = (1 + 2) * bar(3); if (foo > 1) return 4; }";
+        let main = parse(src).functions.pop().unwrap();
+        let text = |span: Span| &src[span.start..span.end];
+        assert_eq!(text(main.span), src);
+
+        let stmt = &main.body[0];
+        assert_eq!(text(stmt.span), "int *p = &foo;");
+        if let StmtKind::Decl(decls) = &stmt.kind {
+            assert_eq!(text(decls[0].span), "*p = &foo");
+        } else {
+            panic!("expected a declaration");
+        }
+
+        let stmt = &main.body[1];
+        assert_eq!(text(stmt.span), "foo = (1 + 2) * bar(3);");
+        let expr = match &stmt.kind {
+            StmtKind::Expr(expr) => expr,
+            _ => panic!("expected an expression statement"),
+        };
+        assert_eq!(text(expr.span), "foo = (1 + 2) * bar(3)");
+        let (lhs, rhs) = match &expr.kind {
+            ExprKind::Assign(lhs, rhs) => (lhs, rhs),
+            _ => panic!("expected an assignment"),
+        };
+        assert_eq!(text(lhs.span), "foo");
+        assert_eq!(text(rhs.span), "(1 + 2) * bar(3)");
+        if let ExprKind::Binary(BinOp::Mul, lhs, rhs) = &rhs.kind {
+            assert_eq!(text(lhs.span), "(1 + 2)");
+            assert_eq!(text(rhs.span), "bar(3)");
+        } else {
+            panic!("expected `*`");
+        }
+
+        let stmt = &main.body[2];
+        assert_eq!(text(stmt.span), "if (foo > 1) return 4;");
+        if let StmtKind::If {cond, then, ..} = &stmt.kind {
+            assert_eq!(text(cond.span), "foo > 1");
+            assert_eq!(text(then.span), "return 4;");
+        } else {
+            panic!("expected `if`");
+        }
+    }
+
+    #[test]
+    fn test_error() {
+        assert_eq!(error("int main() { 1 + 2 }"), (1, 20, "expected `;`".to_string()));
+        assert_eq!(error("int main() { 1 = 2; }"), (1, 14, "not an lvalue".to_string()));
+        assert_eq!(error("int main() { a = (b + 1) = 2; }"), (1, 18, "not an lvalue".to_string()));
+        assert_eq!(error("int main() { &1; }"), (1, 15, "not an lvalue".to_string()));
+        assert_eq!(error("int main() { &&a; }"), (1, 14, "expected an expression".to_string()));
+        assert_eq!(error("int main() { 1 += 2; }"), (1, 14, "not an lvalue".to_string()));
+        assert_eq!(error("int main() { a++ ++; }"), (1, 14, "not an lvalue".to_string()));
+        assert_eq!(error("int main() { ++-a; }"), (1, 16, "not an lvalue".to_string()));
+        assert_eq!(error("int main() { a ? b; }"), (1, 19, "expected `:`".to_string()));
+        assert_eq!(error("int main() { *1 = 2; &*1; &(a = 1); }"), (1, 28, "not an lvalue".to_string()));
+        assert_eq!(error("int main() { 1 <"), (1, 17, "expected an expression".to_string()));
+        assert_eq!(error("int main() { a = (1 + 2; }"), (1, 24, "expected `)`".to_string()));
+        assert_eq!(error("int main() { 1; 2 + ; }"), (1, 21, "expected an expression".to_string()));
+        assert_eq!(error("int main() { return; }"), (1, 20, "expected an expression".to_string()));
+        assert_eq!(error("int main() { return = 1; }"), (1, 21, "expected an expression".to_string()));
+        assert_eq!(error("int main() { if 1; 2; }"), (1, 17, "expected `(`".to_string()));
+        assert_eq!(error("int main() { for (;) 1; }"), (1, 20, "expected an expression".to_string()));
+        assert_eq!(error("int main() { while (1) else 2; }"), (1, 24, "expected an expression".to_string()));
+        assert_eq!(error("int main() { { 1; "), (1, 19, "expected `}`".to_string()));
+        assert_eq!(error("1;"), (1, 1, "expected a type name".to_string()));
+        assert_eq!(error("int 1() {}"), (1, 5, "expected an identifier".to_string()));
+        assert_eq!(error("int main() return 1;"), (1, 12, "expected `{`".to_string()));
+        assert_eq!(error("int f(int a, 1) {}"), (1, 14, "expected a type name".to_string()));
+        assert_eq!(error("int f(int a b) {}"), (1, 13, "expected `,`".to_string()));
+        assert_eq!(error("int f(a) {}"), (1, 7, "expected a type name".to_string()));
+        assert_eq!(
+            error("int f(int a,int b,int c,int d,int e,int f,int g) {}"),
+            (1, 43, "too many parameters".to_string()),
+        );
+        assert_eq!(error("int main() { int 1; }"), (1, 18, "expected an identifier".to_string()));
+        assert_eq!(error("int main() { int a b; }"), (1, 20, "expected `,`".to_string()));
+        assert_eq!(error("int main() { int a = ; }"), (1, 22, "expected an expression".to_string()));
+        assert_eq!(error("int main() { if (1) int a; }"), (1, 21, "expected an expression".to_string()));
+        assert_eq!(error("int main() { int a[x]; }"), (1, 20, "expected an array length".to_string()));
+        assert_eq!(error("int main() { int a[2; }"), (1, 21, "expected `]`".to_string()));
+        assert_eq!(error("int main() { sizeof(int[4611686018427387904]); }"), (1, 25, "array is too large".to_string()));
+        assert_eq!(error("char a[4611686018427387904];"), (1, 8, "array is too large".to_string()));
+        assert_eq!(error("char a[2][1073741824];"), (1, 8, "array is too large".to_string()));
+        assert_eq!(error("int main() { a[1; }"), (1, 17, "expected `]`".to_string()));
+        assert_eq!(error("int main() { sizeof(int a); }"), (1, 25, "expected `)`".to_string()));
+        assert_eq!(error("int x = 1"), (1, 10, "expected `,`".to_string()));
+        assert_eq!(error("int x = {1 2};"), (1, 12, "expected `,`".to_string()));
+        assert_eq!(error("int x = {1,,};"), (1, 12, "expected an expression".to_string()));
+        assert_eq!(error("int f(...);"), (1, 7, "expected a type name".to_string()));
+        assert_eq!(error("int f(int a, ..., int b);"), (1, 17, "expected `)`".to_string()));
+        assert_eq!(error("int main() { va_start(ap); }"), (1, 25, "expected `,`".to_string()));
+        assert_eq!(error("int main() { va_start(ap, 1); }"), (1, 27, "expected an identifier".to_string()));
+        assert_eq!(error("int main() { va_arg(ap, x); }"), (1, 25, "expected a type name".to_string()));
+        assert_eq!(error("int main() { va_list ap = 1 + va_list; }"), (1, 31, "expected an expression".to_string()));
+        assert_eq!(error("int main() { short long a; }"), (1, 14, "invalid type".to_string()));
+        assert_eq!(error("int main() { signed unsigned a; }"), (1, 14, "invalid type".to_string()));
+        assert_eq!(error("long long long f() {}"), (1, 1, "invalid type".to_string()));
+        assert_eq!(error("char int c;"), (1, 1, "invalid type".to_string()));
+        assert_eq!(error("int main() { (int)1 = 2; }"), (1, 14, "not an lvalue".to_string()));
+        assert_eq!(error("int main() { (int 1; }"), (1, 19, "expected `)`".to_string()));
+        assert_eq!(error("struct s {int a;}; struct s {int b;};"), (1, 27, "redefinition of `s`".to_string()));
+        assert_eq!(error("struct s {int a;}; union s u;"), (1, 26, "wrong kind of tag `s`".to_string()));
+        assert_eq!(error("struct s {int a; char a;};"), (1, 23, "duplicate member `a`".to_string()));
+        assert_eq!(error("struct s {struct s a;};"), (1, 20, "member `a` has an incomplete type".to_string()));
+        assert_eq!(error("struct s {char a[2000000000]; char b[2000000000];};"), (1, 8, "struct is too large".to_string()));
+        assert_eq!(error("struct s; int x = sizeof(struct s);"), (1, 19, "incomplete type".to_string()));
+        assert_eq!(error("struct s; int x = _Alignof(struct s);"), (1, 19, "incomplete type".to_string()));
+        assert_eq!(error("struct;"), (1, 7, "expected `{`".to_string()));
+        assert_eq!(error("int main() { a.1; }"), (1, 16, "expected an identifier".to_string()));
+        assert_eq!(error("int main() { a->; }"), (1, 17, "expected an identifier".to_string()));
+    }
+
+    fn parse(s: &str) -> Program {
+        let mut sources = SourceMap::new();
+        let file = sources.add(None, s.to_string());
+        let tokens = sources.tokenize(file).unwrap();
+        Parser::new(&sources, tokens).parse().unwrap()
+    }
+
+    // parse `s` as the body of `main`
+    fn parse_main(s: &str) -> Function {
+        parse(&format!("int main() {{ {} }}", s)).functions.pop().unwrap()
+    }
+
+    // (line, column, message) of the parse error
+    fn error(s: &str) -> (usize, usize, String) {
+        let mut sources = SourceMap::new();
+        let file = sources.add(None, s.to_string());
+        let tokens = sources.tokenize(file).unwrap();
+        let err = Parser::new(&sources, tokens).parse().unwrap_err();
+        let loc = err.location().unwrap();
+        (loc.line, loc.column, err.message().to_string())
+    }
+
+    // render expression as S-expression
+    fn expr_sexp(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Num(n) => n.to_string(),
+            ExprKind::Str(bytes) => format!("{:?}", String::from_utf8_lossy(bytes)),
+            ExprKind::Var {name, ..} => name.clone(),
+            ExprKind::Unary(op, operand) => {
+                format!("({} {})", format!("{:?}", op).to_lowercase(), expr_sexp(operand))
+            },
+            ExprKind::Binary(op, lhs, rhs) => format!("({} {} {})", op.symbol(), expr_sexp(lhs), expr_sexp(rhs)),
+            ExprKind::Assign(lhs, rhs) => format!("(= {} {})", expr_sexp(lhs), expr_sexp(rhs)),
+            ExprKind::AssignOp(op, lhs, rhs) => {
+                format!("({}= {} {})", op.symbol(), expr_sexp(lhs), expr_sexp(rhs))
+            },
+            ExprKind::PostIncDec(op, operand) => format!("(post{} {})", op.symbol().repeat(2), expr_sexp(operand)),
+            ExprKind::Cond {cond, then, els} => {
+                format!("(? {} {} {})", expr_sexp(cond), expr_sexp(then), expr_sexp(els))
+            },
+            ExprKind::Comma(lhs, rhs) => format!("(, {} {})", expr_sexp(lhs), expr_sexp(rhs)),
+            ExprKind::FuncCall {name, args} => {
+                let args: Vec<String> = std::iter::once(name.clone())
+                    .chain(args.iter().map(expr_sexp))
+                    .collect();
+                format!("({})", args.join(" "))
+            },
+            ExprKind::Sizeof(operand) => format!("(sizeof {})", expr_sexp(operand)),
+            ExprKind::VaStart(ap) => format!("(va_start {})", expr_sexp(ap)),
+            ExprKind::VaArg(ap, ty) => format!("(va_arg {} {:?})", expr_sexp(ap), ty),
+            ExprKind::Cast(operand) => format!("(cast {:?} {})", expr.ty(), expr_sexp(operand)),
+            ExprKind::Member {base, name, ..} => format!("(. {} {})", expr_sexp(base), name),
+        }
+    }
+
+    // render statement as S-expression
+    fn to_sexp(stmt: &Stmt) -> String {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => expr_sexp(expr),
+            StmtKind::Return(expr) => format!("(return {})", expr_sexp(expr)),
+            StmtKind::If {cond, then, els} => match els {
+                Some(els) => format!("(if {} {} {})", expr_sexp(cond), to_sexp(then), to_sexp(els)),
+                None => format!("(if {} {})", expr_sexp(cond), to_sexp(then)),
+            },
+            StmtKind::Block(body) => {
+                let stmts: Vec<String> = body.iter().map(to_sexp).collect();
+                format!("{{{}}}", stmts.join(" "))
+            },
+            StmtKind::While {cond, body} => format!("(while {} {})", expr_sexp(cond), to_sexp(body)),
+            StmtKind::For {init, cond, inc, body} => {
+                let opt = |e: &Option<Box<Expr>>| e.as_ref().map_or("_".to_string(), |e| expr_sexp(e));
+                format!("(for {} {} {} {})", opt(init), opt(cond), opt(inc), to_sexp(body))
+            },
+            StmtKind::Decl(decls) => {
+                let decls: Vec<String> = std::iter::once("decl".to_string())
+                    .chain(decls.iter().map(|decl| {
+                        let mut declarator = decl.var.name.clone();
+                        let mut ty = &decl.var.ty;
+                        loop {
+                            match ty {
+                                Type::Ptr(base) => {
+                                    declarator = format!("(* {})", declarator);
+                                    ty = base;
+                                },
+                                Type::Array(base, len) => {
+                                    declarator = format!("([{}] {})", len, declarator);
+                                    ty = base;
+                                },
+                                _ => break,
+                            }
+                        }
+                        match &decl.init {
+                            Some(init) => format!("{} {}", declarator, expr_sexp(init)),
+                            None => declarator,
+                        }
+                    }))
+                    .collect();
+                format!("({})", decls.join(" "))
+            },
+        }
+    }
+
+    fn sexp(s: &str) -> String {
+        parse_main(s).body.iter().map(to_sexp).collect::<Vec<String>>().join(" ")
+    }
+
+    fn test_parse(s: &str) {
+        for stmt in parse_main(s).body {
+            println!("{:?}", stmt);
+        }
+        println!("------------");
+    }
+}
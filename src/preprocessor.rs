@@ -0,0 +1,859 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::error::CompileError;
+use crate::lexer::{Lexer, Span, Token, TokenKind};
+use crate::parser::Parser;
+use crate::sema::Sema;
+use crate::source::SourceMap;
+use crate::types::Type;
+
+type PreprocessResult<T> = Result<T, CompileError>;
+
+// limit of nested `#include`, to stop recursive inclusion
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+// blank lines written by `to_text` before a line marker is used instead
+const MAX_BLANK_LINES: usize = 8;
+
+struct Macro {
+    // `None` for an object-like macro
+    params: Option<Vec<String>>,
+    // whether the last parameter is `...`, named `__VA_ARGS__`
+    variadic: bool,
+    body: Vec<Token>,
+}
+
+#[derive(PartialEq)]
+enum CondContext {
+    Then,
+    Elif,
+    Else,
+}
+
+// an `#if` group being processed
+struct CondIncl {
+    ctx: CondContext,
+    // whether any group of it has been included
+    included: bool,
+    span: Span,
+}
+
+// Preprocessor executes directives and expands macros on the token stream.
+// Included files are added to the `SourceMap`.
+pub struct Preprocessor<'a> {
+    sources: &'a mut SourceMap,
+    include_paths: Vec<PathBuf>,
+    macros: HashMap<String, Macro>,
+    conds: Vec<CondIncl>,
+    // number of the conditional directives opened before the current file
+    cond_base: usize,
+    // tokens to be processed, the next one last
+    input: Vec<Token>,
+    // number of the `#include`s being processed
+    include_depth: usize,
+}
+
+impl<'a> Preprocessor<'a> {
+    // `include_paths` are searched for `#include <...>`, and for `#include "..."` after the directory of the file.
+    pub fn new(sources: &'a mut SourceMap, include_paths: Vec<PathBuf>) -> Self {
+        Self {
+            sources,
+            include_paths,
+            macros: HashMap::new(),
+            conds: Vec::new(),
+            cond_base: 0,
+            input: Vec::new(),
+            include_depth: 0,
+        }
+    }
+
+    // Preprocess the file `file` and return the resulting tokens, ending with `TokenKind::Eof`.
+    pub fn preprocess(&mut self, file: usize) -> PreprocessResult<Vec<Token>> {
+        let mut output = Vec::new();
+        let eof = self.process_file(file, &mut output)?;
+        output.push(eof);
+        Ok(output)
+    }
+
+    // Preprocess the file `file` into `output` and return its `TokenKind::Eof`.
+    // Conditional directives must be terminated in the same file.
+    fn process_file(&mut self, file: usize, output: &mut Vec<Token>) -> PreprocessResult<Token> {
+        let mut tokens = self.sources.tokenize(file)?;
+        let eof = tokens.pop().unwrap();
+        let saved = std::mem::take(&mut self.input);
+        let cond_base = std::mem::replace(&mut self.cond_base, self.conds.len());
+        self.push_input(tokens);
+        let result = self.process(output);
+        self.input = saved;
+        let conds = std::mem::replace(&mut self.cond_base, cond_base);
+        result?;
+        if self.conds.len() > conds {
+            let span = self.conds[conds].span;
+            return Err(self.sources.error_at(span, "unterminated conditional directive"));
+        }
+        Ok(eof)
+    }
+
+    // preprocess the input into `output`
+    fn process(&mut self, output: &mut Vec<Token>) -> PreprocessResult<()> {
+        while let Some(token) = self.input.pop() {
+            if self.expand_macro(&token)? {
+                continue;
+            }
+            if token.is("#") && token.at_bol() {
+                self.directive(token, output)?;
+                continue;
+            }
+            output.push(token);
+        }
+        Ok(())
+    }
+
+    // push `tokens` to be read before the rest of the input
+    fn push_input(&mut self, tokens: Vec<Token>) {
+        self.input.extend(tokens.into_iter().rev());
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.input.last()
+    }
+
+    // whether the next token is on the line being read
+    fn on_line(&self) -> bool {
+        self.peek().is_some_and(|token| !token.at_bol())
+    }
+
+    // the rest of the line
+    fn read_line(&mut self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        while self.on_line() {
+            tokens.push(self.input.pop().unwrap());
+        }
+        tokens
+    }
+
+    // Execute the directive after `#`. Included tokens are appended to `output`.
+    fn directive(&mut self, hash: Token, output: &mut Vec<Token>) -> PreprocessResult<()> {
+        // the null directive
+        if !self.on_line() {
+            return Ok(());
+        }
+        let token = self.input.pop().unwrap();
+        let name = token.ident().unwrap_or("").to_string();
+        match &name as &str {
+            "include" => self.include(&token, output),
+            "define" => self.define(&token),
+            "undef" => {
+                let name = self.macro_name(&token)?;
+                self.read_line();
+                self.macros.remove(&name);
+                Ok(())
+            },
+            "if" => {
+                let value = self.eval_line(&token)?;
+                self.push_cond(value, hash.span())
+            },
+            "ifdef" | "ifndef" => {
+                let name = self.macro_name(&token)?;
+                let defined = self.macros.contains_key(&name);
+                self.read_line();
+                self.push_cond(defined == (token.ident() == Some("ifdef")), hash.span())
+            },
+            "elif" => {
+                match self.current_cond() {
+                    Some(cond) if cond.ctx != CondContext::Else => {},
+                    _ => return Err(self.sources.error_at(hash.span(), "stray #elif")),
+                }
+                let included = self.conds.last().unwrap().included;
+                self.conds.last_mut().unwrap().ctx = CondContext::Elif;
+                // the condition is evaluated only if no group has been included
+                if !included && self.eval_line(&token)? {
+                    self.conds.last_mut().unwrap().included = true;
+                } else {
+                    self.skip_cond_incl();
+                }
+                Ok(())
+            },
+            "else" => {
+                let cond = match self.current_cond() {
+                    Some(cond) if cond.ctx != CondContext::Else => cond,
+                    _ => return Err(self.sources.error_at(hash.span(), "stray #else")),
+                };
+                cond.ctx = CondContext::Else;
+                let included = cond.included;
+                self.read_line();
+                if included {
+                    self.skip_cond_incl();
+                }
+                Ok(())
+            },
+            "endif" => {
+                if self.current_cond().is_none() {
+                    return Err(self.sources.error_at(hash.span(), "stray #endif"));
+                }
+                self.conds.pop();
+                self.read_line();
+                Ok(())
+            },
+            "error" => {
+                let message = join_tokens(&self.read_line());
+                Err(self.sources.error_at(hash.span(), &format!("#error {}", message)))
+            },
+            // Line markers `# <line> "<file>"` written by `-E` are accepted as the input,
+            // though they do not change the positions of the tokens.
+            _ if matches!(token.kind(), TokenKind::Num(..)) => {
+                self.read_line();
+                Ok(())
+            },
+            // no pragmas are supported
+            "pragma" => {
+                self.read_line();
+                Ok(())
+            },
+            _ => Err(self.sources.error_at(token.span(), "invalid preprocessor directive")),
+        }
+    }
+
+    // the macro name after `directive`
+    fn macro_name(&mut self, directive: &Token) -> PreprocessResult<String> {
+        match self.peek() {
+            Some(token) if !token.at_bol() && token.ident().is_some() => {
+                Ok(self.input.pop().unwrap().ident().unwrap().to_string())
+            },
+            _ => Err(self.sources.error_at(directive.span(), "macro name must be an identifier")),
+        }
+    }
+
+    // `#define name body` or `#define name(params) body`
+    fn define(&mut self, directive: &Token) -> PreprocessResult<()> {
+        let name = self.macro_name(directive)?;
+        // parameters must follow the name without spaces
+        let is_function = self.on_line() && self.peek().is_some_and(|token| token.is("(") && !token.has_space());
+        let mut params = None;
+        let mut variadic = false;
+        if is_function {
+            let lparen = self.input.pop().unwrap();
+            let mut names = Vec::new();
+            loop {
+                let token = match self.input.pop() {
+                    Some(token) if !token.at_bol() => token,
+                    _ => return Err(self.sources.error_at(lparen.span(), "unterminated macro parameter list")),
+                };
+                if token.is(")") && names.is_empty() && !variadic {
+                    break;
+                }
+                if token.is("...") {
+                    variadic = true;
+                } else if let Some(param) = token.ident() {
+                    names.push(param.to_string());
+                } else {
+                    return Err(self.sources.error_at(token.span(), "expected a parameter name"));
+                }
+                match self.input.pop() {
+                    Some(token) if token.is(")") && !token.at_bol() => break,
+                    Some(token) if token.is(",") && !token.at_bol() && !variadic => {},
+                    _ => return Err(self.sources.error_at(lparen.span(), "unterminated macro parameter list")),
+                }
+            }
+            if variadic {
+                names.push("__VA_ARGS__".to_string());
+            }
+            params = Some(names);
+        }
+        let body = self.read_line();
+        self.macros.insert(name, Macro {params, variadic, body});
+        Ok(())
+    }
+
+    // `#include "file"` or `#include <file>`
+    fn include(&mut self, directive: &Token, output: &mut Vec<Token>) -> PreprocessResult<()> {
+        let mut line = self.read_line();
+        // the file name can be given by a macro
+        if !matches!(line.first().map(Token::kind), Some(TokenKind::Str(_))) && !line.first().is_some_and(|t| t.is("<")) {
+            line = self.expand_tokens(line)?;
+        }
+        let (name, quoted) = match line.first().map(Token::kind) {
+            Some(TokenKind::Str(bytes)) if line.len() == 1 => (String::from_utf8_lossy(bytes).to_string(), true),
+            Some(_) if line[0].is("<") && line.last().unwrap().is(">") && line.len() > 2 => {
+                (join_tokens(&line[1..line.len() - 1]).replace(' ', ""), false)
+            },
+            _ => return Err(self.sources.error_at(directive.span(), "expected a file name")),
+        };
+
+        // quoted names are first searched relative to the including file
+        let mut dirs = Vec::new();
+        if quoted {
+            let current = self.sources.name(directive.span().file)
+                .and_then(|current| Path::new(current).parent())
+                .map_or_else(PathBuf::new, Path::to_path_buf);
+            dirs.push(current);
+        }
+        dirs.extend(self.include_paths.iter().cloned());
+        let path = match dirs.iter().map(|dir| dir.join(&name)).find(|path| path.is_file()) {
+            Some(path) => path,
+            None => return Err(self.sources.error_at(directive.span(), &format!("cannot open include file `{}`", name))),
+        };
+
+        if self.include_depth >= MAX_INCLUDE_DEPTH {
+            return Err(self.sources.error_at(directive.span(), "#include nested too deeply"));
+        }
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| self.sources.error_at(directive.span(), &format!("cannot read `{}`: {}", name, e)))?;
+        let file = self.sources.add(Some(path.to_string_lossy().to_string()), contents);
+        self.include_depth += 1;
+        self.process_file(file, output)?;
+        self.include_depth -= 1;
+        Ok(())
+    }
+
+    fn push_cond(&mut self, included: bool, span: Span) -> PreprocessResult<()> {
+        self.conds.push(CondIncl {ctx: CondContext::Then, included, span});
+        if !included {
+            self.skip_cond_incl();
+        }
+        Ok(())
+    }
+
+    // Skip tokens until `#elif`, `#else` or `#endif` of the current `#if`, which are left to be read.
+    // Nested `#if` groups are skipped entirely.
+    // the innermost conditional directive opened in the current file
+    fn current_cond(&mut self) -> Option<&mut CondIncl> {
+        if self.conds.len() > self.cond_base {
+            self.conds.last_mut()
+        } else {
+            None
+        }
+    }
+
+    fn skip_cond_incl(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.input.pop() {
+            let is_directive = token.is("#") && token.at_bol();
+            let name = match self.peek() {
+                Some(next) if is_directive && !next.at_bol() => next.ident().unwrap_or("").to_string(),
+                _ => continue,
+            };
+            match &name as &str {
+                "if" | "ifdef" | "ifndef" => depth += 1,
+                "elif" | "else" if depth == 0 => {
+                    self.input.push(token);
+                    return;
+                },
+                "endif" if depth == 0 => {
+                    self.input.push(token);
+                    return;
+                },
+                "endif" => depth -= 1,
+                _ => {},
+            }
+        }
+    }
+
+    // Evaluate the rest of the line as the condition of `#if` or `#elif`.
+    fn eval_line(&mut self, directive: &Token) -> PreprocessResult<bool> {
+        let line = self.read_line();
+        if line.is_empty() {
+            return Err(self.sources.error_at(directive.span(), "no expression"));
+        }
+
+        // `defined name` and `defined(name)` are replaced before macro expansion
+        let mut tokens = Vec::new();
+        let mut iter = line.into_iter();
+        while let Some(token) = iter.next() {
+            if token.ident() != Some("defined") {
+                tokens.push(token);
+                continue;
+            }
+            let mut name = iter.next();
+            let paren = name.as_ref().is_some_and(|name| name.is("("));
+            if paren {
+                name = iter.next();
+            }
+            let name = match name.as_ref().and_then(Token::ident) {
+                Some(name) => name.to_string(),
+                None => return Err(self.sources.error_at(token.span(), "macro name must be an identifier")),
+            };
+            if paren && !iter.next().is_some_and(|token| token.is(")")) {
+                return Err(self.sources.error_at(token.span(), "expected `)`"));
+            }
+            let value = self.macros.contains_key(&name) as u64;
+            tokens.push(with_kind(&token, TokenKind::Num(value, Type::Int)));
+        }
+
+        // Remaining identifiers are 0.
+        // The expression is computed in `long` or `unsigned long`.
+        let mut tokens: Vec<Token> = self.expand_tokens(tokens)?.iter().map(|token| match token.kind() {
+            TokenKind::Ident(_) | TokenKind::Keyword(_) => with_kind(token, TokenKind::Num(0, Type::Long)),
+            TokenKind::Num(n, ty) => {
+                let ty = if ty.is_unsigned() { Type::ULong } else { Type::Long };
+                with_kind(token, TokenKind::Num(*n, ty))
+            },
+            _ => token.clone(),
+        }).collect();
+        let end = tokens.last().unwrap().span();
+        tokens.push(Token::new(TokenKind::Eof, Span {start: end.end, ..end}));
+
+        let expr = Parser::new(&*self.sources, tokens).parse_const_expr()?;
+        Ok(Sema::new(&*self.sources).const_value(expr)? != 0)
+    }
+
+    // Return `tokens` with all macros expanded.
+    fn expand_tokens(&mut self, tokens: Vec<Token>) -> PreprocessResult<Vec<Token>> {
+        let saved = std::mem::take(&mut self.input);
+        self.push_input(tokens);
+        let mut output = Vec::new();
+        let result = self.expand_input(&mut output);
+        self.input = saved;
+        result.map(|_| output)
+    }
+
+    // expand macros in the input into `output`, without directives
+    fn expand_input(&mut self, output: &mut Vec<Token>) -> PreprocessResult<()> {
+        while let Some(token) = self.input.pop() {
+            if !self.expand_macro(&token)? {
+                output.push(token);
+            }
+        }
+        Ok(())
+    }
+
+    // If `token` is a macro invocation, push its expansion to the input and return true.
+    // Expanded tokens have the span of `token`, so that errors and `__LINE__` refer to the invocation.
+    fn expand_macro(&mut self, token: &Token) -> PreprocessResult<bool> {
+        let name = match token.ident() {
+            Some(name) if !token.hideset().iter().any(|hidden| hidden == name) => name.to_string(),
+            _ => return Ok(false),
+        };
+
+        let builtin = match &name as &str {
+            "__FILE__" => {
+                let file = file_name(self.sources, token.span().file);
+                Some(TokenKind::Str(file.as_bytes().to_vec()))
+            },
+            "__LINE__" => Some(TokenKind::Num(self.sources.line(token.span()) as u64, Type::Int)),
+            _ => None,
+        };
+        if let Some(kind) = builtin {
+            self.input.push(with_kind(token, kind));
+            return Ok(true);
+        }
+
+        let m = match self.macros.get(&name) {
+            Some(m) => m,
+            None => return Ok(false),
+        };
+        let (mut tokens, mut hideset) = match &m.params {
+            None => {
+                let body = m.body.clone();
+                (self.paste_body(&body)?, token.hideset().to_vec())
+            },
+            // a function-like macro name not followed by `(` is an identifier
+            Some(_) if !self.peek().is_some_and(|next| next.is("(")) => return Ok(false),
+            Some(params) => {
+                let params = params.clone();
+                let variadic = m.variadic;
+                let body = m.body.clone();
+                let (args, rparen) = self.read_args(token, &name, params.len(), variadic)?;
+                let tokens = self.substitute(&body, &params, &args)?;
+                // the hideset of `f(...)` is the intersection of those of `f` and `)`
+                let hideset = token.hideset().iter()
+                    .filter(|hidden| rparen.hideset().contains(hidden))
+                    .cloned()
+                    .collect();
+                (tokens, hideset)
+            },
+        };
+        hideset.push(name);
+
+        for expanded in &mut tokens {
+            let mut set = expanded.hideset().to_vec();
+            set.extend(hideset.iter().cloned());
+            expanded.set_hideset(set);
+            expanded.set_span(token.span());
+        }
+        if let Some(first) = tokens.first_mut() {
+            first.set_spacing(token.at_bol(), token.has_space());
+        }
+        self.push_input(tokens);
+        Ok(true)
+    }
+
+    // Read the arguments of a function-like macro `name` after `token`, and return them with `)`.
+    // The arguments of `...` are read as one argument with their commas.
+    fn read_args(
+        &mut self, token: &Token, name: &str, nparams: usize, variadic: bool,
+    ) -> PreprocessResult<(Vec<Vec<Token>>, Token)> {
+        self.input.pop();
+        let mut args = vec![Vec::new()];
+        let mut depth = 0;
+        let rparen = loop {
+            let arg = match self.input.pop() {
+                Some(arg) => arg,
+                None => return Err(self.sources.error_at(token.span(), "unterminated macro invocation")),
+            };
+            if depth == 0 && arg.is(")") {
+                break arg;
+            }
+            if depth == 0 && arg.is(",") && !(variadic && args.len() == nparams) {
+                args.push(Vec::new());
+                continue;
+            }
+            if arg.is("(") {
+                depth += 1;
+            } else if arg.is(")") {
+                depth -= 1;
+            }
+            args.last_mut().unwrap().push(arg);
+        };
+
+        // `f()` has no arguments, and `...` can be empty
+        if nparams == 0 && args.len() == 1 && args[0].is_empty() {
+            args.clear();
+        }
+        if variadic && args.len() == nparams - 1 {
+            args.push(Vec::new());
+        }
+        if args.len() != nparams {
+            let message = format!("wrong number of arguments to macro `{}`", name);
+            return Err(self.sources.error_at(token.span(), &message));
+        }
+        Ok((args, rparen))
+    }
+
+    // Replace the parameters in `body` with the arguments.
+    // Arguments are macro-expanded, except for the operands of `#` and `##`.
+    fn substitute(&mut self, body: &[Token], params: &[String], args: &[Vec<Token>]) -> PreprocessResult<Vec<Token>> {
+        let arg_of = |token: &Token| {
+            token.ident().and_then(|name| params.iter().position(|param| param == name)).map(|i| &args[i])
+        };
+        let mut output: Vec<Token> = Vec::new();
+        let mut i = 0;
+        while i < body.len() {
+            let token = &body[i];
+            let next = body.get(i + 1);
+
+            // `#param` is the string of the argument
+            if token.is("#") {
+                match next.and_then(arg_of) {
+                    Some(arg) => {
+                        let s = join_tokens(arg);
+                        output.push(with_kind(token, TokenKind::Str(s.into_bytes())));
+                        i += 2;
+                        continue;
+                    },
+                    None => return Err(self.sources.error_at(token.span(), "`#` is not followed by a macro parameter")),
+                }
+            }
+
+            // `lhs ## rhs` makes one token of `lhs` and `rhs`
+            if token.is("##") {
+                let rhs = match next {
+                    Some(rhs) if !output.is_empty() => rhs,
+                    _ => {
+                        let message = "`##` cannot appear at either end of macro expansion";
+                        return Err(self.sources.error_at(token.span(), message));
+                    },
+                };
+                match arg_of(rhs) {
+                    Some(arg) if arg.is_empty() => {},
+                    Some(arg) => {
+                        let lhs = output.pop().unwrap();
+                        output.push(self.paste(&lhs, &arg[0])?);
+                        output.extend(arg[1..].iter().cloned());
+                    },
+                    None => {
+                        let lhs = output.pop().unwrap();
+                        output.push(self.paste(&lhs, rhs)?);
+                    },
+                }
+                i += 2;
+                continue;
+            }
+
+            if let Some(arg) = arg_of(token) {
+                if next.is_some_and(|next| next.is("##")) {
+                    // an empty argument before `##` leaves the other operand as it is
+                    if arg.is_empty() {
+                        match body.get(i + 2) {
+                            Some(rhs) => match arg_of(rhs) {
+                                Some(rhs_arg) => output.extend(rhs_arg.iter().cloned()),
+                                None => output.push(rhs.clone()),
+                            },
+                            None => {
+                                let message = "`##` cannot appear at either end of macro expansion";
+                                return Err(self.sources.error_at(body[i + 1].span(), message));
+                            },
+                        }
+                        i += 3;
+                    } else {
+                        output.extend(arg.iter().cloned());
+                        i += 1;
+                    }
+                    continue;
+                }
+                let mut expanded = self.expand_tokens(arg.clone())?;
+                if let Some(first) = expanded.first_mut() {
+                    first.set_spacing(token.at_bol(), token.has_space());
+                }
+                output.extend(expanded);
+                i += 1;
+                continue;
+            }
+
+            output.push(token.clone());
+            i += 1;
+        }
+        Ok(output)
+    }
+
+    // Paste the operands of `##` in the body of an object-like macro.
+    fn paste_body(&self, body: &[Token]) -> PreprocessResult<Vec<Token>> {
+        let mut output: Vec<Token> = Vec::new();
+        let mut iter = body.iter();
+        while let Some(token) = iter.next() {
+            if !token.is("##") {
+                output.push(token.clone());
+                continue;
+            }
+            match (output.pop(), iter.next()) {
+                (Some(lhs), Some(rhs)) => output.push(self.paste(&lhs, rhs)?),
+                _ => {
+                    let message = "`##` cannot appear at either end of macro expansion";
+                    return Err(self.sources.error_at(token.span(), message));
+                },
+            }
+        }
+        Ok(output)
+    }
+
+    // one token of `lhs` and `rhs` written together
+    fn paste(&self, lhs: &Token, rhs: &Token) -> PreprocessResult<Token> {
+        let s = format!("{}{}", lhs.kind(), rhs.kind());
+        let invalid = || {
+            let message = format!("pasting forms `{}`, an invalid token", s);
+            self.sources.error_at(lhs.span(), &message)
+        };
+        let tokens = Lexer::new(&s).tokenize().map_err(|_| invalid())?;
+        if tokens.len() != 2 {
+            return Err(invalid());
+        }
+        Ok(with_kind(lhs, tokens[0].kind().clone()))
+    }
+}
+
+// `token` with `kind` replaced
+fn with_kind(token: &Token, kind: TokenKind) -> Token {
+    let mut new = Token::new(kind, token.span());
+    new.set_spacing(token.at_bol(), token.has_space());
+    new.set_hideset(token.hideset().to_vec());
+    new
+}
+
+// name of the file `file` for `__FILE__` and line markers
+fn file_name(sources: &SourceMap, file: usize) -> &str {
+    sources.name(file).unwrap_or("<input>")
+}
+
+// C source text of preprocessed `tokens`, in the lines and the files they come from.
+// A line marker `# <line> "<file>"` is written where the file changes or many lines are skipped.
+pub fn to_text(sources: &SourceMap, tokens: &[Token]) -> String {
+    let mut s = String::new();
+    // the file and the line being written
+    let mut current: Option<(usize, usize)> = None;
+    let mut prev: Option<&Token> = None;
+    for token in tokens {
+        if let TokenKind::Eof = token.kind() {
+            break;
+        }
+        let span = token.span();
+        let line = sources.line(span);
+        match current {
+            Some((file, current_line)) if file == span.file && line <= current_line => {
+                if token.has_space() || prev.is_some_and(|prev| would_paste(prev, token)) {
+                    s.push(' ');
+                }
+            },
+            Some((file, current_line)) if file == span.file && line - current_line <= MAX_BLANK_LINES => {
+                s.push_str(&"\n".repeat(line - current_line));
+                s.push_str(&" ".repeat(sources.column(span) - 1));
+            },
+            _ => {
+                if !s.is_empty() {
+                    s.push('\n');
+                }
+                let name = TokenKind::Str(file_name(sources, span.file).as_bytes().to_vec());
+                s.push_str(&format!("# {} {}\n", line, name));
+                s.push_str(&" ".repeat(sources.column(span) - 1));
+            },
+        }
+        s.push_str(&token.kind().to_string());
+        current = Some((span.file, line));
+        prev = Some(token);
+    }
+    if !s.is_empty() {
+        s.push('\n');
+    }
+    s
+}
+
+// whether `lhs` and `rhs` written together are read as other tokens, like `-` and `-` from `-f(-1)`
+fn would_paste(lhs: &Token, rhs: &Token) -> bool {
+    let s = format!("{}{}", lhs.kind(), rhs.kind());
+    match Lexer::new(&s).tokenize() {
+        Ok(tokens) => tokens.len() != 3 || tokens[0].kind() != lhs.kind() || tokens[1].kind() != rhs.kind(),
+        Err(_) => true,
+    }
+}
+
+// C source text of `tokens` in a line, with a space where they are separated
+pub fn join_tokens(tokens: &[Token]) -> String {
+    let mut s = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 && token.has_space() {
+            s.push(' ');
+        }
+        s.push_str(&token.kind().to_string());
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn preprocess_with(s: &str, include_paths: Vec<PathBuf>) -> PreprocessResult<String> {
+        let mut sources = SourceMap::new();
+        let file = sources.add(None, s.to_string());
+        let tokens = Preprocessor::new(&mut sources, include_paths).preprocess(file)?;
+        Ok(join_tokens(&tokens[..tokens.len() - 1]))
+    }
+
+    fn preprocess(s: &str) -> PreprocessResult<String> {
+        preprocess_with(s, Vec::new())
+    }
+
+    #[test]
+    fn test_macro() {
+        assert_eq!(preprocess("#define N 3\nN + N").unwrap(), "3 + 3");
+        assert_eq!(preprocess("#define N 3\n#undef N\nN").unwrap(), "N");
+        assert_eq!(preprocess("#define f(x, y) (x * y)\nf(1 + 2, (3, 4))").unwrap(), "(1 + 2 * (3, 4))");
+        // not an invocation without `(`
+        assert_eq!(preprocess("#define f(x) x\nf + 1").unwrap(), "f + 1");
+        // object-like if `(` is separated from the name
+        assert_eq!(preprocess("#define f (x) x\nf").unwrap(), "(x) x");
+        // no recursive expansion
+        assert_eq!(preprocess("#define x x + 1\nx").unwrap(), "x + 1");
+        assert_eq!(preprocess("#define f(x) g(x)\n#define g(x) f(x) + x\nf(1)").unwrap(), "f(1) + 1");
+        assert_eq!(preprocess("#define f() 1\nf()").unwrap(), "1");
+        assert_eq!(preprocess("#define f(...) g(__VA_ARGS__)\nf() f(1, 2)").unwrap(), "g() g(1, 2)");
+        assert_eq!(preprocess("#define f(x, ...) x: __VA_ARGS__\nf(1) f(1, 2, 3)").unwrap(), "1: 1: 2, 3");
+        assert_eq!(preprocess("#define s(x) #x\ns(a  +\"b\\n\")").unwrap(), r#""a +\"b\\n\"""#);
+        assert_eq!(preprocess("#define cat(x, y) x ## y\ncat(a, 1) cat(, b) cat(c,) cat(<, <=)").unwrap(), "a1 b c <<=");
+        assert_eq!(preprocess("#define AB a ## b\nint AB;").unwrap(), "int ab;");
+        assert_eq!(preprocess("#define N 1 ## 2 ## 3 + 4\nN").unwrap(), "123 + 4");
+        assert_eq!(preprocess("int x;\n__LINE__ __FILE__\n#define L __LINE__\n\nL").unwrap(), "int x; 2 \"<input>\" 5");
+    }
+
+    #[test]
+    fn test_conditional() {
+        assert_eq!(preprocess("#if 1\na\n#else\nb\n#endif").unwrap(), "a");
+        assert_eq!(preprocess("#if 0\na\n#elif 2 > 1\nb\n#else\nc\n#endif").unwrap(), "b");
+        assert_eq!(preprocess("#if 0\n#if 1\na\n#else\nb\n#endif\n#else\nc\n#endif").unwrap(), "c");
+        assert_eq!(preprocess("#define A\n#ifdef A\na\n#endif\n#ifndef A\nb\n#endif").unwrap(), "a");
+        assert_eq!(preprocess("#define N 2\n#if defined(N) && N * 2 == 4 && !defined M\na\n#endif").unwrap(), "a");
+        // undefined names are 0, and the arithmetic is in 64 bits
+        assert_eq!(preprocess("#if X || 1 << 40 == 0\na\n#endif").unwrap(), "");
+        assert_eq!(preprocess("#if -1 > 0u\na\n#endif").unwrap(), "a");
+        // directives in skipped groups are not executed
+        assert_eq!(preprocess("#if 0\n#error no\n#endif").unwrap(), "");
+        // line markers are skipped
+        assert_eq!(preprocess("# 1 \"a.c\"\na").unwrap(), "a");
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("compiler-v1-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sys")).unwrap();
+        fs::write(dir.join("a.h"), "#define A 1\nint a;\n").unwrap();
+        fs::write(dir.join("sys/b.h"), "#include \"c.h\"\nint b = B;\n").unwrap();
+        fs::write(dir.join("sys/c.h"), "#define B 2\n").unwrap();
+        fs::write(dir.join("d.h"), "#if 1\n").unwrap();
+        fs::write(dir.join("e.h"), "int e = __LINE__ $\n").unwrap();
+        fs::write(dir.join("f.h"), "#endif\n").unwrap();
+        fs::write(dir.join("g.h"), "#else\n").unwrap();
+        fs::write(dir.join("h.h"), "#elif 1\n").unwrap();
+
+        let paths = vec![dir.clone()];
+        let s = "#include <a.h>\n#include <sys/b.h>\n#define H \"a.h\"\n#include H\nA";
+        assert_eq!(preprocess_with(s, paths.clone()).unwrap(), "int a; int b = 2; int a; 1");
+
+        let err = preprocess("#include \"none.h\"").unwrap_err();
+        assert_eq!(err.to_string(), "1: #include \"none.h\"\n    ^ cannot open include file `none.h`");
+        let err = preprocess_with("#include <d.h>\n#endif", paths.clone()).unwrap_err();
+        assert_eq!(err.message(), "unterminated conditional directive");
+        assert_eq!(err.file_name(), Some(dir.join("d.h").to_str().unwrap()));
+        // an included file can't close the includer's conditional
+        for (file, message) in &[("f.h", "stray #endif"), ("g.h", "stray #else"), ("h.h", "stray #elif")] {
+            let err = preprocess_with(&format!("#if 1\n#include <{}>\n#endif", file), paths.clone()).unwrap_err();
+            assert_eq!(err.message(), *message);
+            assert_eq!(err.file_name(), Some(dir.join(file).to_str().unwrap()));
+        }
+        let err = preprocess_with("#include <e.h>", paths).unwrap_err();
+        assert_eq!(err.file_name(), Some(dir.join("e.h").to_str().unwrap()));
+        assert_eq!(err.message(), "invalid token");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_to_text() {
+        let text = |s: &str, include_paths: Vec<PathBuf>| {
+            let mut sources = SourceMap::new();
+            let file = sources.add(Some("main.c".to_string()), s.to_string());
+            let tokens = Preprocessor::new(&mut sources, include_paths).preprocess(file).unwrap();
+            to_text(&sources, &tokens)
+        };
+        assert_eq!(text("", Vec::new()), "");
+        assert_eq!(text("int x;\n\n  int y ;", Vec::new()), "# 1 \"main.c\"\nint x;\n\n  int y ;\n");
+        // the expansion is in the line of the invocation
+        assert_eq!(text("#define f(x) -x\nf(-1) f(\n2)+1\nx", Vec::new()), "# 2 \"main.c\"\n- -1 -2\n  +1\nx\n");
+        let s = "#define cat(x, y) x ## y\n#define d(x) x/\ncat(a, b) cat(1, 2) d(/) cat(+, =)";
+        assert_eq!(text(s, Vec::new()), "# 3 \"main.c\"\nab 12 / / +=\n");
+        assert_eq!(text("a\n\n\n\n\n\n\n\n\n\nb", Vec::new()), "# 1 \"main.c\"\na\n# 11 \"main.c\"\nb\n");
+
+        let dir = std::env::temp_dir().join(format!("compiler-v1-text-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.h"), "\nint a;\n").unwrap();
+        let header = dir.join("a.h").to_str().unwrap().to_string();
+        let expected = format!("# 1 \"main.c\"\nint x;\n# 2 \"{}\"\nint a;\n# 3 \"main.c\"\nint y;\n", header);
+        assert_eq!(text("int x;\n#include <a.h>\nint y;", vec![dir.clone()]), expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_error() {
+        let cases = [
+            ("#error stop  here", "#error stop here"),
+            ("#foo", "invalid preprocessor directive"),
+            ("# \"a.c\"", "invalid preprocessor directive"),
+            ("#define 1", "macro name must be an identifier"),
+            ("#define f(x 1", "unterminated macro parameter list"),
+            ("#define f(x) x\nf(1, 2)", "wrong number of arguments to macro `f`"),
+            ("#define f(x) x\nf(1", "unterminated macro invocation"),
+            ("#define f(x) #y\nf(1)", "`#` is not followed by a macro parameter"),
+            ("#define f(x) ## x\nf(1)", "`##` cannot appear at either end of macro expansion"),
+            ("#define N 1 ##\nN", "`##` cannot appear at either end of macro expansion"),
+            ("#define f(x) x ## +\nf(-)", "pasting forms `-+`, an invalid token"),
+            ("#if 1\n", "unterminated conditional directive"),
+            ("#endif", "stray #endif"),
+            ("#if 1\n#else\n#else\n#endif", "stray #else"),
+            ("#if\n#endif", "no expression"),
+            ("#if 1 / 0\n#endif", "division by zero"),
+            ("#if defined(\n#endif", "macro name must be an identifier"),
+        ];
+        for (s, message) in &cases {
+            assert_eq!(preprocess(s).unwrap_err().message(), *message, "{}", s);
+        }
+    }
+}
@@ -0,0 +1,1318 @@
+use crate::error::CompileError;
+use crate::node::{
+    BinOp, Data, Declaration, Expr, ExprKind, Function, GVar, GlobalDecl, Initializer, LVar, Program,
+    Stmt, StmtKind, Symbol, UnaryOp, Var,
+};
+use crate::lexer::Span;
+use crate::source::SourceMap;
+use crate::types::{StructKind, Type};
+use crate::utils::align_to;
+
+type SemaResult<T> = Result<T, CompileError>;
+
+// Sema resolves variable names, assigns types to expressions,
+// places local variables in the stack frame and computes initial data of global variables.
+// Implicit conversions are made explicit as `ExprKind::Cast`,
+// and pointer arithmetic is rewritten to work on bytes.
+pub struct Sema<'a> {
+    sources: &'a SourceMap,
+    // functions declared or defined in the program
+    functions: Vec<FuncSig>,
+    // global variables, visible from everywhere in the program
+    globals: Vec<GVar>,
+    // all local variables of the current function
+    locals: Vec<LVar>,
+    // symbol table of local variables, the innermost block scope last
+    scopes: Vec<Vec<LVar>>,
+    // return type of the current function
+    ret_ty: Type,
+    // register save area of the current function, if variadic
+    va_area: Option<usize>,
+}
+
+// Register save area of System V ABI for `va_start`,
+// where the 6 general-purpose registers and the 8 XMM registers are saved.
+const REG_SAVE_AREA_SIZE: usize = 6 * 8 + 8 * 16;
+
+// Signature of a function
+struct FuncSig {
+    name: String,
+    ret_ty: Type,
+    params: Vec<Type>,
+    variadic: bool,
+    // whether it has a definition, not only declarations
+    defined: bool,
+}
+
+impl<'a> Sema<'a> {
+    // `sources` are the files the program was parsed from, used for error messages.
+    pub fn new(sources: &'a SourceMap) -> Self {
+        Self {
+            sources,
+            functions: Vec::new(),
+            globals: Vec::new(),
+            locals: Vec::new(),
+            scopes: Vec::new(),
+            ret_ty: Type::Int,
+            va_area: None,
+        }
+    }
+
+    pub fn check(&mut self, program: Program) -> SemaResult<Program> {
+        // functions and global variables share one namespace
+        for decl in &program.declarations {
+            if decl.ret_ty.is_struct() {
+                return Err(self.error_at(decl.span, "returning a struct is not supported"));
+            }
+            let params = decl.params.iter().map(|param| param.var.ty.clone()).collect();
+            let sig = FuncSig {
+                name: decl.name.clone(),
+                ret_ty: decl.ret_ty.clone(),
+                params,
+                variadic: decl.variadic,
+                defined: false,
+            };
+            self.declare_function(sig, decl.span)?;
+        }
+        for function in &program.functions {
+            let params = function.params.iter().map(|param| param.var.ty.clone()).collect();
+            let sig = FuncSig {
+                name: function.name.clone(),
+                ret_ty: function.ret_ty.clone(),
+                params,
+                variadic: function.variadic,
+                defined: true,
+            };
+            self.declare_function(sig, function.span)?;
+        }
+        for global in &program.globals {
+            self.declare_global(&global.var.name, global.span)?;
+            self.globals.push(global.var.clone());
+        }
+
+        let globals = program.globals.into_iter()
+            .map(|global| self.global(global))
+            .collect::<SemaResult<_>>()?;
+        let functions = program.functions.into_iter()
+            .map(|function| self.function(function))
+            .collect::<SemaResult<_>>()?;
+        Ok(Program {globals, functions, declarations: program.declarations, structs: program.structs})
+    }
+
+    // Return the value of the integer constant expression `expr`.
+    pub fn const_value(&mut self, expr: Expr) -> SemaResult<i64> {
+        let span = expr.span;
+        let expr = self.expr(expr)?;
+        match self.eval(&expr)? {
+            (None, value) if expr.ty().is_integer() => Ok(value),
+            _ => Err(self.error_at(span, "not an integer constant expression")),
+        }
+    }
+
+    // A function can be declared any number of times with the same signature, and defined once.
+    fn declare_function(&mut self, sig: FuncSig, span: Span) -> SemaResult<()> {
+        let declared = match self.functions.iter_mut().find(|declared| declared.name == sig.name) {
+            Some(declared) => declared,
+            None => {
+                self.functions.push(sig);
+                return Ok(());
+            },
+        };
+        if declared.defined && sig.defined {
+            let message = format!("redefinition of `{}`", sig.name);
+            return Err(self.sources.error_at(span, &message));
+        }
+        if (&declared.ret_ty, &declared.params, declared.variadic) != (&sig.ret_ty, &sig.params, sig.variadic) {
+            let message = format!("conflicting types for `{}`", sig.name);
+            return Err(self.sources.error_at(span, &message));
+        }
+        declared.defined |= sig.defined;
+        Ok(())
+    }
+
+    fn declare_global(&self, name: &str, span: Span) -> SemaResult<()> {
+        let defined = self.functions.iter().any(|function| function.name == name)
+            || self.globals.iter().any(|var| var.name == name);
+        if defined {
+            return Err(self.error_at(span, &format!("redefinition of `{}`", name)));
+        }
+        Ok(())
+    }
+
+    fn error_at(&self, span: Span, message: &str) -> CompileError {
+        self.sources.error_at(span, message)
+    }
+
+    fn function(&mut self, function: Function) -> SemaResult<Function> {
+        // each function has its own local variables
+        self.locals = Vec::new();
+        self.scopes = vec![Vec::new()];
+        self.ret_ty = function.ret_ty.clone();
+
+        // structs are passed in memory by the ABI, which is not supported
+        if function.ret_ty.is_struct() {
+            return Err(self.error_at(function.span, "returning a struct is not supported"));
+        }
+        if let Some(param) = function.params.iter().find(|param| param.var.ty.is_struct()) {
+            return Err(self.error_at(param.span, "struct parameter is not supported"));
+        }
+
+        let params = function.params.into_iter()
+            .map(|param| self.declaration(param))
+            .collect::<SemaResult<_>>()?;
+        self.va_area = None;
+        if function.variadic {
+            let ty = Type::array_of(Type::Long, REG_SAVE_AREA_SIZE / 8);
+            let mut area = LVar {name: String::new(), offset: 0, ty};
+            self.alloc_local(&mut area, function.span)?;
+            self.va_area = Some(area.offset);
+        }
+        let body = self.stmts(function.body)?;
+        Ok(Function {
+            params,
+            body,
+            locals: std::mem::take(&mut self.locals),
+            va_area: self.va_area,
+            ..function
+        })
+    }
+
+    // Add the variable of `decl` to the innermost scope and give it a stack slot.
+    // It shadows variables of the same name in outer scopes.
+    fn declaration(&mut self, decl: Declaration) -> SemaResult<Declaration> {
+        let Declaration {mut var, init, span} = decl;
+        let scope = self.scopes.last().unwrap();
+        if scope.iter().any(|declared| declared.name == var.name) {
+            return Err(self.error_at(span, &format!("redefinition of `{}`", var.name)));
+        }
+        if var.ty.is_incomplete() {
+            return Err(self.error_at(span, &format!("variable `{}` has an incomplete type", var.name)));
+        }
+        self.alloc_local(&mut var, span)?;
+        self.scopes.last_mut().unwrap().push(var.clone());
+
+        if init.is_some() && var.ty.is_array() {
+            return Err(self.error_at(span, "array initializer is not supported"));
+        }
+        let init = match init {
+            Some(init) => {
+                let init = self.expr(init)?;
+                Some(self.convert_to(init, &var.ty)?)
+            },
+            None => None,
+        };
+        Ok(Declaration {var, init, span})
+    }
+
+    // Give `var` a stack slot below the other local variables.
+    // The frame must be addressable by 32-bit displacements from `rbp`.
+    fn alloc_local(&mut self, var: &mut LVar, span: Span) -> SemaResult<()> {
+        let bottom = self.locals.last().map_or(0, |last| last.offset);
+        var.offset = align_to(bottom + var.ty.size(), var.ty.align());
+        if align_to(var.offset, 16) > i32::MAX as usize {
+            return Err(self.error_at(span, "stack frame is too large"));
+        }
+        self.locals.push(var.clone());
+        Ok(())
+    }
+
+    // Return the variable `name` visible from the current scope.
+    // Local variables shadow global ones.
+    fn find_var(&self, name: &str) -> Option<Var> {
+        let local = self.scopes.iter().rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|var| var.name == name);
+        if let Some(var) = local {
+            return Some(Var::Local(var.clone()));
+        }
+        self.globals.iter()
+            .find(|var| var.name == name)
+            .map(|var| Var::Global(var.clone()))
+    }
+
+    fn global(&mut self, global: GlobalDecl) -> SemaResult<GlobalDecl> {
+        let GlobalDecl {var, init, span, ..} = global;
+        if var.ty.is_incomplete() {
+            return Err(self.error_at(span, &format!("variable `{}` has an incomplete type", var.name)));
+        }
+        let (init, data) = match init {
+            Some(init) => {
+                let mut data = Vec::new();
+                let init = self.initializer(&var.ty, init, &mut data)?;
+                (Some(init), Some(data))
+            },
+            None => (None, None),
+        };
+        Ok(GlobalDecl {var, init, data, span})
+    }
+
+    // Append the data of a value of `ty` initialized with `init`, and return the checked `init`.
+    // Elements missing in the initializer are zero-filled.
+    fn initializer(&mut self, ty: &Type, init: Initializer, data: &mut Vec<Data>) -> SemaResult<Initializer> {
+        match (ty, init) {
+            // `char s[n] = "..."`
+            (Type::Array(base, len), Initializer::Expr(expr))
+                if **base == Type::Char && matches!(expr.kind, ExprKind::Str(_)) =>
+            {
+                let expr = self.expr(expr)?;
+                let mut bytes = match &expr.kind {
+                    ExprKind::Str(bytes) => bytes.clone(),
+                    _ => unreachable!(),
+                };
+                // the terminating NUL can be omitted
+                if bytes.len() > *len {
+                    return Err(self.error_at(expr.span, "initializer string is too long"));
+                }
+                bytes.resize(*len, 0);
+                data.push(Data::Bytes(bytes));
+                Ok(Initializer::Expr(expr))
+            },
+            (Type::Array(base, len), Initializer::List(items, span)) => {
+                if items.len() > *len {
+                    return Err(self.error_at(span, "excess elements in array initializer"));
+                }
+                let rest = len - items.len();
+                let items = items.into_iter()
+                    .map(|item| self.initializer(base, item, data))
+                    .collect::<SemaResult<_>>()?;
+                if rest > 0 {
+                    data.push(Data::Bytes(vec![0; base.size() * rest]));
+                }
+                Ok(Initializer::List(items, span))
+            },
+            // members are initialized in order, and a union is initialized by the first member
+            (Type::Struct(struct_ty), Initializer::List(items, span)) => {
+                let len = match struct_ty.kind() {
+                    StructKind::Struct => struct_ty.members().len(),
+                    StructKind::Union => 1,
+                };
+                if items.len() > len {
+                    return Err(self.error_at(span, "excess elements in struct initializer"));
+                }
+                let mut end = 0;
+                let mut checked = Vec::new();
+                for (member, item) in struct_ty.members().iter().zip(items) {
+                    // padding before the member
+                    if member.offset > end {
+                        data.push(Data::Bytes(vec![0; member.offset - end]));
+                    }
+                    checked.push(self.initializer(&member.ty, item, data)?);
+                    end = member.offset + member.ty.size();
+                }
+                if ty.size() > end {
+                    data.push(Data::Bytes(vec![0; ty.size() - end]));
+                }
+                Ok(Initializer::List(checked, span))
+            },
+            (Type::Array(..), Initializer::Expr(expr)) => Err(self.error_at(expr.span, "invalid initializer")),
+            (_, Initializer::List(_, span)) => Err(self.error_at(span, "invalid initializer")),
+            (_, Initializer::Expr(expr)) => {
+                let expr = self.expr(expr)?;
+                let expr = self.convert_to(expr, ty)?;
+                match self.eval(&expr)? {
+                    (None, value) => {
+                        data.push(Data::Bytes(value.to_le_bytes()[..ty.size()].to_vec()));
+                    },
+                    (Some(symbol), offset) if ty.size() == 8 => data.push(Data::Addr(symbol, offset)),
+                    (Some(_), _) => return Err(self.error_at(expr.span, "initializer element is not constant")),
+                }
+                Ok(Initializer::Expr(expr))
+            },
+        }
+    }
+
+    // Evaluate the constant expression `expr`,
+    // which is a number or an address of a symbol plus a number.
+    fn eval(&self, expr: &Expr) -> SemaResult<(Option<Symbol>, i64)> {
+        let not_constant = || self.error_at(expr.span, "initializer element is not constant");
+        match &expr.kind {
+            ExprKind::Num(n) => Ok((None, *n)),
+            // arrays are converted to pointers to their first elements
+            ExprKind::Var {..} | ExprKind::Str(_) if expr.ty().is_array() => self.eval_addr(expr),
+            ExprKind::Unary(UnaryOp::Addr, operand) => self.eval_addr(operand),
+            ExprKind::Unary(UnaryOp::Neg, operand) => match self.eval(operand)? {
+                (None, n) => Ok((None, convert(n.wrapping_neg(), expr.ty()))),
+                _ => Err(not_constant()),
+            },
+            ExprKind::Unary(UnaryOp::Not, operand) => match self.eval(operand)? {
+                (None, n) => Ok((None, (n == 0) as i64)),
+                _ => Err(not_constant()),
+            },
+            ExprKind::Unary(UnaryOp::BitNot, operand) => match self.eval(operand)? {
+                (None, n) => Ok((None, convert(!n, expr.ty()))),
+                _ => Err(not_constant()),
+            },
+            ExprKind::Cond {cond, then, els} => match self.eval(cond)? {
+                (None, 0) => self.eval(els),
+                (None, _) => self.eval(then),
+                _ => Err(not_constant()),
+            },
+            // addresses do not fit in smaller types
+            ExprKind::Cast(operand) => match self.eval(operand)? {
+                (None, n) => Ok((None, convert(n, expr.ty()))),
+                (symbol, n) if expr.ty().size() == 8 => Ok((symbol, n)),
+                _ => Err(not_constant()),
+            },
+            ExprKind::Binary(op, lhs, rhs) => {
+                // operands have been converted to the same type
+                let unsigned = lhs.ty().is_unsigned();
+                let (lhs_symbol, lhs) = self.eval(lhs)?;
+                let (rhs_symbol, rhs) = self.eval(rhs)?;
+                let (symbol, value) = match (op, lhs_symbol, rhs_symbol) {
+                    (BinOp::Add, symbol, None) | (BinOp::Add, None, symbol) => (symbol, lhs.wrapping_add(rhs)),
+                    (BinOp::Sub, symbol, None) => (symbol, lhs.wrapping_sub(rhs)),
+                    (_, None, None) => {
+                        let value = match op {
+                            BinOp::Mul => lhs.wrapping_mul(rhs),
+                            BinOp::Div if rhs == 0 => return Err(self.error_at(expr.span, "division by zero")),
+                            BinOp::Div if unsigned => ((lhs as u64) / (rhs as u64)) as i64,
+                            BinOp::Div => lhs.wrapping_div(rhs),
+                            BinOp::Mod if rhs == 0 => return Err(self.error_at(expr.span, "division by zero")),
+                            BinOp::Mod if unsigned => ((lhs as u64) % (rhs as u64)) as i64,
+                            BinOp::Mod => lhs.wrapping_rem(rhs),
+                            BinOp::BitAnd => lhs & rhs,
+                            BinOp::BitOr => lhs | rhs,
+                            BinOp::BitXor => lhs ^ rhs,
+                            // the shift count is taken modulo 64 as x86-64 does
+                            BinOp::Shl => lhs.wrapping_shl(rhs as u32),
+                            BinOp::Shr if unsigned => (lhs as u64).wrapping_shr(rhs as u32) as i64,
+                            BinOp::Shr => lhs.wrapping_shr(rhs as u32),
+                            BinOp::Eq => (lhs == rhs) as i64,
+                            BinOp::Ne => (lhs != rhs) as i64,
+                            BinOp::Lt if unsigned => ((lhs as u64) < (rhs as u64)) as i64,
+                            BinOp::Lt => (lhs < rhs) as i64,
+                            BinOp::Le if unsigned => ((lhs as u64) <= (rhs as u64)) as i64,
+                            BinOp::Le => (lhs <= rhs) as i64,
+                            BinOp::LogAnd => (lhs != 0 && rhs != 0) as i64,
+                            BinOp::LogOr => (lhs != 0 || rhs != 0) as i64,
+                            BinOp::Add | BinOp::Sub => unreachable!(),
+                        };
+                        (None, value)
+                    },
+                    _ => return Err(not_constant()),
+                };
+                Ok((symbol, convert(value, expr.ty())))
+            },
+            _ => Err(not_constant()),
+        }
+    }
+
+    // evaluate the address of the lvalue `expr` as a constant
+    fn eval_addr(&self, expr: &Expr) -> SemaResult<(Option<Symbol>, i64)> {
+        match &expr.kind {
+            ExprKind::Var {var: Some(Var::Global(var)), ..} => Ok((Some(Symbol::Global(var.name.clone())), 0)),
+            ExprKind::Str(bytes) => Ok((Some(Symbol::Str(bytes.clone())), 0)),
+            ExprKind::Unary(UnaryOp::Deref, operand) => self.eval(operand),
+            _ => Err(self.error_at(expr.span, "initializer element is not constant")),
+        }
+    }
+
+    fn stmts(&mut self, stmts: Vec<Stmt>) -> SemaResult<Vec<Stmt>> {
+        stmts.into_iter().map(|stmt| self.stmt(stmt)).collect()
+    }
+
+    fn stmt(&mut self, stmt: Stmt) -> SemaResult<Stmt> {
+        let kind = match stmt.kind {
+            StmtKind::Expr(expr) => StmtKind::Expr(self.expr(expr)?),
+            StmtKind::Return(expr) => {
+                let expr = self.expr(expr)?;
+                StmtKind::Return(self.convert_to(expr, &self.ret_ty)?)
+            },
+            StmtKind::If {cond, then, els} => StmtKind::If {
+                cond: self.cond(cond)?,
+                then: Box::new(self.stmt(*then)?),
+                els: match els {
+                    Some(els) => Some(Box::new(self.stmt(*els)?)),
+                    None => None,
+                },
+            },
+            StmtKind::While {cond, body} => StmtKind::While {
+                cond: self.cond(cond)?,
+                body: Box::new(self.stmt(*body)?),
+            },
+            StmtKind::For {init, cond, inc, body} => StmtKind::For {
+                init: self.opt_expr(init)?,
+                cond: cond.map(|cond| self.cond(*cond).map(Box::new)).transpose()?,
+                inc: self.opt_expr(inc)?,
+                body: Box::new(self.stmt(*body)?),
+            },
+            StmtKind::Block(body) => {
+                self.scopes.push(Vec::new());
+                let body = self.stmts(body)?;
+                self.scopes.pop();
+                StmtKind::Block(body)
+            },
+            StmtKind::Decl(decls) => {
+                let decls = decls.into_iter()
+                    .map(|decl| self.declaration(decl))
+                    .collect::<SemaResult<_>>()?;
+                StmtKind::Decl(decls)
+            },
+        };
+        Ok(Stmt::new(kind, stmt.span))
+    }
+
+    fn opt_expr(&mut self, expr: Option<Box<Expr>>) -> SemaResult<Option<Box<Expr>>> {
+        expr.map(|expr| self.expr(*expr).map(Box::new)).transpose()
+    }
+
+    // a condition is compared with zero, which a struct can't be
+    fn cond(&mut self, expr: Expr) -> SemaResult<Expr> {
+        let cond = self.expr(expr)?;
+        if cond.ty().is_struct() {
+            return Err(self.error_at(cond.span, "invalid condition"));
+        }
+        Ok(cond)
+    }
+
+    // Return `expr` with types assigned to it and all its subexpressions.
+    fn expr(&mut self, expr: Expr) -> SemaResult<Expr> {
+        let span = expr.span;
+        match expr.kind {
+            // the parser gives the types of literals
+            ExprKind::Num(n) => Ok(num(n, expr.ty.unwrap_or(Type::Int), span)),
+            ExprKind::Str(bytes) => {
+                // with the terminating NUL
+                let ty = Type::array_of(Type::Char, bytes.len() + 1);
+                Ok(Expr::with_type(ExprKind::Str(bytes), span, ty))
+            },
+            ExprKind::Var {name, ..} => {
+                let var = match self.find_var(&name) {
+                    Some(var) => var,
+                    None => {
+                        return Err(self.error_at(span, &format!("undeclared variable `{}`", name)));
+                    },
+                };
+                let ty = var.ty().clone();
+                Ok(Expr::with_type(ExprKind::Var {name, var: Some(var)}, span, ty))
+            },
+            ExprKind::Unary(op, operand) => {
+                let operand = self.expr(*operand)?;
+                let (operand, ty) = match op {
+                    UnaryOp::Neg if operand.ty().is_integer() => {
+                        let ty = promote(operand.ty());
+                        (cast(operand, &ty), ty)
+                    },
+                    UnaryOp::Neg => return Err(self.error_at(span, "invalid operand to `-`")),
+                    UnaryOp::Not if !operand.ty().is_struct() => (operand, Type::Int),
+                    UnaryOp::Not => return Err(self.error_at(span, "invalid operand to `!`")),
+                    UnaryOp::BitNot if operand.ty().is_integer() => {
+                        let ty = promote(operand.ty());
+                        (cast(operand, &ty), ty)
+                    },
+                    UnaryOp::BitNot => return Err(self.error_at(span, "invalid operand to `~`")),
+                    UnaryOp::Addr => {
+                        let ty = Type::pointer_to(operand.ty().clone());
+                        (operand, ty)
+                    },
+                    UnaryOp::Deref => match operand.ty().base() {
+                        Some(base) => {
+                            let ty = base.clone();
+                            (operand, ty)
+                        },
+                        None => return Err(self.error_at(span, "invalid pointer dereference")),
+                    },
+                };
+                Ok(Expr::with_type(ExprKind::Unary(op, Box::new(operand)), span, ty))
+            },
+            ExprKind::Binary(op, lhs, rhs) => {
+                let lhs = self.expr(*lhs)?;
+                let rhs = self.expr(*rhs)?;
+                self.new_binary(op, lhs, rhs, span)
+            },
+            ExprKind::Assign(lhs, rhs) => {
+                let lhs = self.expr(*lhs)?;
+                if lhs.ty().is_array() {
+                    return Err(self.error_at(lhs.span, "not an lvalue"));
+                }
+                let ty = lhs.ty().clone();
+                let rhs = self.expr(*rhs)?;
+                let rhs = self.convert_to(rhs, &ty)?;
+                Ok(Expr::with_type(ExprKind::Assign(Box::new(lhs), Box::new(rhs)), span, ty))
+            },
+            ExprKind::AssignOp(op, lhs, rhs) => {
+                let lhs = self.expr(*lhs)?;
+                let rhs = self.expr(*rhs)?;
+                self.new_assign_op(op, lhs, rhs, span)
+            },
+            // `x++` is `(x += 1) - 1` converted back to the type of `x`
+            ExprKind::PostIncDec(op, operand) => {
+                let operand = self.expr(*operand)?;
+                let ty = operand.ty().clone();
+                let inverse = if op == BinOp::Add { BinOp::Sub } else { BinOp::Add };
+                let value = self.new_assign_op(op, operand, num(1, Type::Int, span), span)?;
+                let value = self.new_binary(inverse, value, num(1, Type::Int, span), span)?;
+                Ok(cast(value, &ty))
+            },
+            ExprKind::Cond {cond, then, els} => {
+                let cond = self.cond(*cond)?;
+                let then = self.expr(*then)?;
+                let els = self.expr(*els)?;
+                let (then, els, ty) = match (then.ty(), els.ty()) {
+                    (lhs, rhs) if lhs.is_integer() && rhs.is_integer() => {
+                        let ty = common_type(lhs, rhs);
+                        (cast(then, &ty), cast(els, &ty), ty)
+                    },
+                    // arrays are converted to pointers to their first elements
+                    (Type::Ptr(base) | Type::Array(base, _), _) | (_, Type::Ptr(base) | Type::Array(base, _)) => {
+                        let ty = Type::pointer_to((**base).clone());
+                        (then, els, ty)
+                    },
+                    (lhs, rhs) if lhs.is_struct() && lhs == rhs => {
+                        let ty = lhs.clone();
+                        (then, els, ty)
+                    },
+                    _ => return Err(self.error_at(span, "type mismatch in conditional expression")),
+                };
+                let kind = ExprKind::Cond {cond: Box::new(cond), then: Box::new(then), els: Box::new(els)};
+                Ok(Expr::with_type(kind, span, ty))
+            },
+            ExprKind::Comma(lhs, rhs) => {
+                let lhs = self.expr(*lhs)?;
+                let rhs = self.expr(*rhs)?;
+                let ty = rhs.ty().clone();
+                Ok(Expr::with_type(ExprKind::Comma(Box::new(lhs), Box::new(rhs)), span, ty))
+            },
+            ExprKind::Member {base, name, ..} => {
+                let base = self.expr(*base)?;
+                let struct_ty = match base.ty() {
+                    Type::Struct(struct_ty) => struct_ty,
+                    _ => return Err(self.error_at(span, "member reference to a non-struct value")),
+                };
+                if !struct_ty.is_complete() {
+                    return Err(self.error_at(span, "member reference to an incomplete type"));
+                }
+                let member = match struct_ty.member(&name) {
+                    Some(member) => member.clone(),
+                    None => return Err(self.error_at(span, &format!("no member named `{}`", name))),
+                };
+                let ty = member.ty.clone();
+                Ok(Expr::with_type(ExprKind::Member {base: Box::new(base), name, member: Some(member)}, span, ty))
+            },
+            ExprKind::Cast(operand) => {
+                let ty = expr.ty.expect("cast without a type");
+                let operand = self.expr(*operand)?;
+                // only scalars can be converted
+                if ty.is_array() || ty.is_struct() || operand.ty().is_struct() {
+                    return Err(self.error_at(span, "invalid cast"));
+                }
+                let cast = cast(operand, &ty);
+                Ok(Expr {span, ..cast})
+            },
+            ExprKind::FuncCall {name, args} => {
+                let args: Vec<Expr> = args.into_iter()
+                    .map(|arg| self.expr(arg))
+                    .collect::<SemaResult<_>>()?;
+                if let Some(arg) = args.iter().find(|arg| arg.ty().is_struct()) {
+                    return Err(self.error_at(arg.span, "passing a struct is not supported"));
+                }
+                // functions not declared in the program are assumed to return `int`
+                let sig = match self.functions.iter().find(|function| function.name == name) {
+                    Some(sig) => sig,
+                    None => return Ok(Expr::with_type(ExprKind::FuncCall {name, args}, span, Type::Int)),
+                };
+                if args.len() < sig.params.len() || (args.len() > sig.params.len() && !sig.variadic) {
+                    return Err(self.error_at(span, &format!("wrong number of arguments to `{}`", name)));
+                }
+                // the arguments for `...` are promoted
+                let args = args.into_iter().enumerate().map(|(i, arg)| match sig.params.get(i) {
+                    Some(ty) => self.convert_to(arg, ty),
+                    None if arg.ty().is_integer() => {
+                        let ty = promote(arg.ty());
+                        Ok(cast(arg, &ty))
+                    },
+                    None => Ok(arg),
+                }).collect::<SemaResult<_>>()?;
+                Ok(Expr::with_type(ExprKind::FuncCall {name, args}, span, sig.ret_ty.clone()))
+            },
+            ExprKind::VaStart(ap) => {
+                if self.va_area.is_none() {
+                    return Err(self.error_at(span, "`va_start` used in a function with fixed parameters"));
+                }
+                let ap = self.va_list(*ap)?;
+                // the value is unused, as `va_start` returns `void`
+                Ok(Expr::with_type(ExprKind::VaStart(Box::new(ap)), span, Type::Int))
+            },
+            // values are passed as 8 bytes
+            ExprKind::VaArg(ap, ty) => {
+                let ap = self.va_list(*ap)?;
+                if !ty.is_integer() && !ty.is_pointer() {
+                    return Err(self.error_at(span, "unsupported type for `va_arg`"));
+                }
+                Ok(Expr::with_type(ExprKind::VaArg(Box::new(ap), ty.clone()), span, ty))
+            },
+            // the operand is not evaluated
+            ExprKind::Sizeof(operand) => {
+                let operand = self.expr(*operand)?;
+                if operand.ty().is_incomplete() {
+                    return Err(self.error_at(span, "incomplete type"));
+                }
+                Ok(num(operand.ty().size() as i64, Type::ULong, span))
+            },
+        }
+    }
+
+    // check the `va_list` operand of `va_start` or `va_arg`
+    fn va_list(&mut self, expr: Expr) -> SemaResult<Expr> {
+        let expr = self.expr(expr)?;
+        if !expr.ty().is_va_list() {
+            return Err(self.error_at(expr.span, "expected a `va_list`"));
+        }
+        Ok(expr)
+    }
+
+    // Convert `expr` to `ty` as if by assignment.
+    // Structs are not converted but copied, so they must be of the same type.
+    fn convert_to(&self, expr: Expr, ty: &Type) -> SemaResult<Expr> {
+        if (ty.is_struct() || expr.ty().is_struct()) && expr.ty() != ty {
+            return Err(self.error_at(expr.span, "incompatible types"));
+        }
+        Ok(cast(expr, ty))
+    }
+
+    // Convert the integer operands of `op` to their common type by the usual arithmetic conversions.
+    // Return the converted operands and the common type.
+    fn arith_conv(&self, op: BinOp, lhs: Expr, rhs: Expr, span: Span) -> SemaResult<(Expr, Expr, Type)> {
+        if !lhs.ty().is_integer() || !rhs.ty().is_integer() {
+            return Err(self.invalid_operands(op, span));
+        }
+        let ty = common_type(lhs.ty(), rhs.ty());
+        Ok((cast(lhs, &ty), cast(rhs, &ty), ty))
+    }
+
+    fn invalid_operands(&self, op: BinOp, span: Span) -> CompileError {
+        self.error_at(span, &format!("invalid operands to `{}`", op.symbol()))
+    }
+
+    // `lhs op rhs` with the typed operands
+    fn new_binary(&self, op: BinOp, lhs: Expr, rhs: Expr, span: Span) -> SemaResult<Expr> {
+        match op {
+            BinOp::Add => self.new_add(lhs, rhs, span),
+            BinOp::Sub => self.new_sub(lhs, rhs, span),
+            BinOp::Mul | BinOp::Div | BinOp::Mod | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor => {
+                let (lhs, rhs, ty) = self.arith_conv(op, lhs, rhs, span)?;
+                Ok(binary(op, lhs, rhs, span, ty))
+            },
+            // the operands are promoted separately, and the result has the type of `lhs`
+            BinOp::Shl | BinOp::Shr => {
+                if !lhs.ty().is_integer() || !rhs.ty().is_integer() {
+                    return Err(self.invalid_operands(op, span));
+                }
+                let ty = promote(lhs.ty());
+                let rhs_ty = promote(rhs.ty());
+                Ok(binary(op, cast(lhs, &ty), cast(rhs, &rhs_ty), span, ty))
+            },
+            // pointers are compared as they are
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le => {
+                if lhs.ty().is_struct() || rhs.ty().is_struct() {
+                    return Err(self.invalid_operands(op, span));
+                }
+                let (lhs, rhs) = if lhs.ty().is_integer() && rhs.ty().is_integer() {
+                    let (lhs, rhs, _) = self.arith_conv(op, lhs, rhs, span)?;
+                    (lhs, rhs)
+                } else {
+                    (lhs, rhs)
+                };
+                Ok(binary(op, lhs, rhs, span, Type::Int))
+            },
+            // any scalars are tested against zero
+            BinOp::LogAnd | BinOp::LogOr => {
+                if lhs.ty().is_struct() || rhs.ty().is_struct() {
+                    return Err(self.invalid_operands(op, span));
+                }
+                Ok(binary(op, lhs, rhs, span, Type::Int))
+            },
+        }
+    }
+
+    // `lhs op= rhs` is `tmp = &lhs, *tmp = *tmp op rhs`,
+    // where `tmp` is a hidden local variable, so that `lhs` is evaluated once.
+    fn new_assign_op(&mut self, op: BinOp, lhs: Expr, rhs: Expr, span: Span) -> SemaResult<Expr> {
+        if lhs.ty().is_array() {
+            return Err(self.error_at(lhs.span, "not an lvalue"));
+        }
+        let ty = lhs.ty().clone();
+        let ptr_ty = Type::pointer_to(ty.clone());
+        let mut tmp = LVar {name: String::new(), offset: 0, ty: ptr_ty.clone()};
+        self.alloc_local(&mut tmp, span)?;
+        let tmp_var = || {
+            let kind = ExprKind::Var {name: String::new(), var: Some(Var::Local(tmp.clone()))};
+            Expr::with_type(kind, span, ptr_ty.clone())
+        };
+        let deref = || Expr::with_type(ExprKind::Unary(UnaryOp::Deref, Box::new(tmp_var())), span, ty.clone());
+
+        let addr = Expr::with_type(ExprKind::Unary(UnaryOp::Addr, Box::new(lhs)), span, ptr_ty.clone());
+        let init = Expr::with_type(ExprKind::Assign(Box::new(tmp_var()), Box::new(addr)), span, ptr_ty.clone());
+        let value = self.new_binary(op, deref(), rhs, span)?;
+        let value = self.convert_to(value, &ty)?;
+        let assign = Expr::with_type(ExprKind::Assign(Box::new(deref()), Box::new(value)), span, ty.clone());
+        Ok(Expr::with_type(ExprKind::Comma(Box::new(init), Box::new(assign)), span, ty))
+    }
+
+    // `ptr + n` is the address `n` elements after `ptr`.
+    // Arrays are converted to pointers to their first elements.
+    fn new_add(&self, lhs: Expr, rhs: Expr, span: Span) -> SemaResult<Expr> {
+        match (lhs.ty().base(), rhs.ty().base()) {
+            (None, None) => {
+                let (lhs, rhs, ty) = self.arith_conv(BinOp::Add, lhs, rhs, span)?;
+                Ok(binary(BinOp::Add, lhs, rhs, span, ty))
+            },
+            (Some(_), None) if !rhs.ty().is_integer() => Err(self.invalid_operands(BinOp::Add, span)),
+            (Some(base), None) => {
+                if base.is_incomplete() {
+                    return Err(self.error_at(span, "incomplete type"));
+                }
+                let ty = Type::pointer_to(base.clone());
+                let rhs = scale(rhs, base.size());
+                Ok(binary(BinOp::Add, lhs, rhs, span, ty))
+            },
+            // `n + ptr` is `ptr + n`
+            (None, Some(_)) => self.new_add(rhs, lhs, span),
+            (Some(_), Some(_)) => Err(self.invalid_operands(BinOp::Add, span)),
+        }
+    }
+
+    // `ptr - n` is the address `n` elements before `ptr`,
+    // and `ptr - ptr` is the number of elements between them.
+    fn new_sub(&self, lhs: Expr, rhs: Expr, span: Span) -> SemaResult<Expr> {
+        match (lhs.ty().base(), rhs.ty().base()) {
+            (None, None) => {
+                let (lhs, rhs, ty) = self.arith_conv(BinOp::Sub, lhs, rhs, span)?;
+                Ok(binary(BinOp::Sub, lhs, rhs, span, ty))
+            },
+            (Some(_), None) if !rhs.ty().is_integer() => Err(self.invalid_operands(BinOp::Sub, span)),
+            (Some(base), None) => {
+                if base.is_incomplete() {
+                    return Err(self.error_at(span, "incomplete type"));
+                }
+                let ty = Type::pointer_to(base.clone());
+                let rhs = scale(rhs, base.size());
+                Ok(binary(BinOp::Sub, lhs, rhs, span, ty))
+            },
+            // `ptrdiff_t` is `long`
+            (Some(base), Some(_)) => {
+                if base.is_incomplete() {
+                    return Err(self.error_at(span, "incomplete type"));
+                }
+                let size = num(base.size() as i64, Type::Long, span);
+                let bytes = binary(BinOp::Sub, lhs, rhs, span, Type::Long);
+                Ok(binary(BinOp::Div, bytes, size, span, Type::Long))
+            },
+            (None, Some(_)) => Err(self.invalid_operands(BinOp::Sub, span)),
+        }
+    }
+}
+
+fn num(n: i64, ty: Type, span: Span) -> Expr {
+    Expr::with_type(ExprKind::Num(n), span, ty)
+}
+
+fn binary(op: BinOp, lhs: Expr, rhs: Expr, span: Span, ty: Type) -> Expr {
+    Expr::with_type(ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), span, ty)
+}
+
+// `expr * size`, to convert a number of elements to bytes
+fn scale(expr: Expr, size: usize) -> Expr {
+    let span = expr.span;
+    let expr = cast(expr, &Type::Long);
+    binary(BinOp::Mul, expr, num(size as i64, Type::Long, span), span, Type::Long)
+}
+
+// Convert `expr` to `ty`. Numbers are converted in place.
+fn cast(expr: Expr, ty: &Type) -> Expr {
+    if expr.ty() == ty {
+        return expr;
+    }
+    match expr.kind {
+        ExprKind::Num(n) if ty.is_integer() => num(convert(n, ty), ty.clone(), expr.span),
+        _ => {
+            let span = expr.span;
+            Expr::with_type(ExprKind::Cast(Box::new(expr)), span, ty.clone())
+        },
+    }
+}
+
+// value of `n` converted to `ty`, which wraps around if `ty` is an integer type smaller than `i64`
+fn convert(n: i64, ty: &Type) -> i64 {
+    if !ty.is_integer() {
+        return n;
+    }
+    match (ty.size(), ty.is_unsigned()) {
+        (1, false) => n as i8 as i64,
+        (1, true) => n as u8 as i64,
+        (2, false) => n as i16 as i64,
+        (2, true) => n as u16 as i64,
+        (4, false) => n as i32 as i64,
+        (4, true) => n as u32 as i64,
+        _ => n,
+    }
+}
+
+// Integer promotion: types smaller than `int` are converted to `int`.
+fn promote(ty: &Type) -> Type {
+    if ty.size() < Type::Int.size() {
+        Type::Int
+    } else {
+        ty.clone()
+    }
+}
+
+// The usual arithmetic conversions: after the promotion,
+// the larger type wins, and the unsigned one wins if they are of the same size.
+fn common_type(lhs: &Type, rhs: &Type) -> Type {
+    let (lhs, rhs) = (promote(lhs), promote(rhs));
+    if lhs.size() != rhs.size() {
+        return if lhs.size() > rhs.size() { lhs } else { rhs };
+    }
+    if rhs.is_unsigned() { rhs } else { lhs }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_locals() {
+        let main = check_main("int foo = 1; int bar = foo + 2; foo = bar;");
+        let locals: Vec<(&str, usize)> = main.locals.iter()
+            .map(|var| (&var.name as &str, var.offset))
+            .collect();
+        assert_eq!(locals, vec![("foo", 4), ("bar", 8)]);
+        assert_eq!(main.stack_size(), 16);
+        assert_eq!(check_main("int a, b, *c;").stack_size(), 16);
+        assert_eq!(check_main("long a, b, *c;").stack_size(), 32);
+        assert_eq!(check_main("1;").stack_size(), 0);
+
+        // parameters come first
+        let program = check("int f(int a, int b) { int c; return c; }");
+        let offsets: Vec<usize> = program.functions[0].locals.iter().map(|var| var.offset).collect();
+        assert_eq!(offsets, vec![4, 8, 12]);
+        assert_eq!(program.functions[0].params[1].var.offset, 8);
+        assert_eq!(program.functions[0].va_area, None);
+
+        // the register save area of a variadic function follows the parameters
+        let program = check("int f(char a, ...) { va_list ap; }");
+        let offsets: Vec<usize> = program.functions[0].locals.iter().map(|var| var.offset).collect();
+        assert_eq!(offsets, vec![1, 184, 208]);
+        assert_eq!(program.functions[0].va_area, Some(184));
+    }
+
+    #[test]
+    fn test_scope() {
+        // `a` declared in the block is not visible after the block
+        let main = check_main("int a; { int a = 1; int b = a; } a = 2;");
+        let offsets: Vec<usize> = main.locals.iter().map(|var| var.offset).collect();
+        assert_eq!(offsets, vec![4, 8, 12]);
+        let assigned = match &main.body[2].kind {
+            StmtKind::Expr(Expr {kind: ExprKind::Assign(lhs, _), ..}) => lhs,
+            _ => panic!("expected an assignment"),
+        };
+        match &assigned.kind {
+            ExprKind::Var {var: Some(Var::Local(var)), ..} => assert_eq!(var.offset, 4),
+            _ => panic!("expected a resolved variable"),
+        }
+
+        // variables of outer scopes are visible in inner blocks
+        let main = check_main("int a; { { a = 2; } }");
+        assert_eq!(main.locals.len(), 1);
+    }
+
+    #[test]
+    fn test_type() {
+        let main = check_main("int a; int *p; a; &a; *&a; &*&a; *p; &a + 1; 1 + &a; &a - 1; &a - &a; p = &a; 1 == &a;");
+        let types: Vec<Type> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some(expr.ty().clone()),
+            _ => None,
+        }).collect();
+        let int = || Type::Int;
+        let ptr = |ty| Type::pointer_to(ty);
+        // the difference of pointers is a `long`
+        assert_eq!(types, vec![
+            int(), ptr(int()), int(), ptr(int()), int(),
+            ptr(int()), ptr(int()), ptr(int()), Type::Long, ptr(int()), int(),
+        ]);
+
+        // calls of functions in the program have their return types
+        let program = check("int *f() { return 0; } int main() { f(); g(); }");
+        let types: Vec<Type> = program.functions[1].body.iter().map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => expr.ty().clone(),
+            _ => panic!("expected an expression statement"),
+        }).collect();
+        assert_eq!(types, vec![ptr(int()), int()]);
+
+        // arguments are converted to the parameter types, and those for `...` are promoted
+        let program = check("char f(char c, long l); int printf(char *fmt, ...); int main() { char c; f(1, c); printf(\"\", c, 1l); }");
+        assert_eq!(program.declarations.len(), 2);
+        let exprs: Vec<(String, Type)> = program.functions[0].body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some((sexp(expr), expr.ty().clone())),
+            _ => None,
+        }).collect();
+        assert_eq!(exprs, vec![
+            ("(f 1 (Long c))".to_string(), Type::Char),
+            ("(printf (Ptr(Char) Str([])) (Int c) 1)".to_string(), int()),
+        ]);
+
+        // arrays decay to pointers in arithmetic
+        let main = check_main("int a[2][3]; a; a[1]; a[1][2]; a + 1; &a; *a + 1; &a[1] - a;");
+        let types: Vec<Type> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some(expr.ty().clone()),
+            _ => None,
+        }).collect();
+        let arr = |ty, len| Type::array_of(ty, len);
+        assert_eq!(types, vec![
+            arr(arr(int(), 3), 2), arr(int(), 3), int(), ptr(arr(int(), 3)),
+            ptr(arr(arr(int(), 3), 2)), ptr(int()), Type::Long,
+        ]);
+
+        let main = check_main(r#"char c; char *p; c; c + 1; *p; p + 1; "ab"; "ab"[0]; 'a';"#);
+        let types: Vec<Type> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some(expr.ty().clone()),
+            _ => None,
+        }).collect();
+        assert_eq!(types, vec![
+            Type::Char, int(), Type::Char, ptr(Type::Char), arr(Type::Char, 3), Type::Char, int(),
+        ]);
+    }
+
+    #[test]
+    fn test_sizeof() {
+        let main = check_main("int x; int *p; int a[2][3]; sizeof x; sizeof p; sizeof a; sizeof a[0]; sizeof(a + 1); sizeof &a; sizeof *p;");
+        let exprs: Vec<String> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some(sexp(expr)),
+            _ => None,
+        }).collect();
+        assert_eq!(exprs, vec!["4", "8", "24", "12", "8", "8", "4"]);
+
+        let main = check_main(r#"char c; char *p; sizeof c; sizeof p; sizeof *p; sizeof "abc"; sizeof 'a';"#);
+        let exprs: Vec<String> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some(sexp(expr)),
+            _ => None,
+        }).collect();
+        assert_eq!(exprs, vec!["1", "8", "1", "4", "4"]);
+
+        // arrays take their whole size in the stack frame
+        let main = check_main("int a[3]; int b; int c[2][2];");
+        let offsets: Vec<usize> = main.locals.iter().map(|var| var.offset).collect();
+        assert_eq!(offsets, vec![12, 16, 32]);
+
+        // variables are aligned
+        let main = check_main("char a; int b; char c[3]; char d; int *e; short f; long g;");
+        let offsets: Vec<usize> = main.locals.iter().map(|var| var.offset).collect();
+        assert_eq!(offsets, vec![1, 8, 11, 12, 24, 26, 40]);
+    }
+
+    #[test]
+    fn test_scale() {
+        let main = check_main("int *p; int **q; p + 1; 2 + p; p - 3; q + 1; p - p; 4 + 5;");
+        let exprs: Vec<String> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some(sexp(expr)),
+            _ => None,
+        }).collect();
+        assert_eq!(exprs, vec![
+            "(Add p (Mul 1 4))",
+            "(Add p (Mul 2 4))",
+            "(Sub p (Mul 3 4))",
+            "(Add q (Mul 1 8))",
+            "(Div (Sub p p) 4)",
+            "(Add 4 5)",
+        ]);
+
+        // the index is converted to `long` before scaling
+        let main = check_main("int *p; char c; p + c;");
+        assert_eq!(sexp(match &main.body[2].kind {
+            StmtKind::Expr(expr) => expr,
+            _ => panic!("expected an expression statement"),
+        }), "(Add p (Mul (Long c) 4))");
+    }
+
+    #[test]
+    fn test_conversion() {
+        let main = check_main("char c; short s; int i; long l; unsigned u; unsigned long ul; \
+            c + c; s * c; i - l; u / i; l + u; ul + l; c == u; -c; -u; i = l; c = 300; (short)i; (char)-1;");
+        let exprs: Vec<(String, Type)> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some((sexp(expr), expr.ty().clone())),
+            _ => None,
+        }).collect();
+        let t = |sexp: &str, ty: Type| (sexp.to_string(), ty);
+        assert_eq!(exprs, vec![
+            t("(Add (Int c) (Int c))", Type::Int),
+            t("(Mul (Int s) (Int c))", Type::Int),
+            t("(Sub (Long i) l)", Type::Long),
+            t("(Div u (UInt i))", Type::UInt),
+            t("(Add l (Long u))", Type::Long),
+            t("(Add ul (ULong l))", Type::ULong),
+            t("(Eq (UInt c) u)", Type::Int),
+            t("(Neg (Int c))", Type::Int),
+            t("(Neg u)", Type::UInt),
+            t("(Assign i (Int l))", Type::Int),
+            // numbers are converted in place
+            t("(Assign c 44)", Type::Char),
+            t("(Short i)", Type::Short),
+            t("(Char (Neg 1))", Type::Char),
+        ]);
+
+        // return values are converted to the return type
+        let program = check("char f() { return 256 + 1; } long g(int x) { return x; }");
+        let returns: Vec<String> = program.functions.iter().map(|function| match &function.body[0].kind {
+            StmtKind::Return(expr) => sexp(expr),
+            _ => panic!("expected `return`"),
+        }).collect();
+        assert_eq!(returns, vec!["(Char (Add 256 1))", "(Long x)"]);
+
+        // literals have the types given by their forms and suffixes
+        let main = check_main("1u + 1; 0xffffffff - 1L; -1 < 1u;");
+        let exprs: Vec<(String, Type)> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some((sexp(expr), expr.ty().clone())),
+            _ => None,
+        }).collect();
+        assert_eq!(exprs, vec![
+            ("(Add 1 1)".to_string(), Type::UInt),
+            ("(Sub 4294967295 1)".to_string(), Type::Long),
+            ("(Lt (UInt (Neg 1)) 1)".to_string(), Type::Int),
+        ]);
+
+        // constants are evaluated in their types
+        let program = check("unsigned char c = 257; int i = (unsigned)-1 / 2; int j = -1 < (unsigned)0; \
+            short s = 65535; long l = 2147483647 + 1; int *p = (int *)(long)8;");
+        let data: Vec<Option<Vec<Data>>> = program.globals.iter().map(|global| global.data.clone()).collect();
+        let bytes = |n: i64, size: usize| Some(vec![Data::Bytes(n.to_le_bytes()[..size].to_vec())]);
+        assert_eq!(data, vec![
+            bytes(1, 1), bytes(i32::MAX as i64, 4), bytes(0, 4), bytes(-1, 2), bytes(-2147483648, 8), bytes(8, 8),
+        ]);
+    }
+
+    #[test]
+    fn test_operators() {
+        let main = check_main("char c; int i; unsigned u; long l; int *p; \
+            c % i; u & l; c << l; u >> c; !p; ~c; c && p; i ? c : u; i ? 0 : p; (c, p);");
+        let exprs: Vec<(String, Type)> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some((sexp(expr), expr.ty().clone())),
+            _ => None,
+        }).collect();
+        let t = |sexp: &str, ty: Type| (sexp.to_string(), ty);
+        assert_eq!(exprs, vec![
+            t("(Mod (Int c) i)", Type::Int),
+            t("(BitAnd (Long u) l)", Type::Long),
+            // shift operands are promoted separately
+            t("(Shl (Int c) l)", Type::Int),
+            t("(Shr u (Int c))", Type::UInt),
+            t("(Not p)", Type::Int),
+            t("(BitNot (Int c))", Type::Int),
+            t("(LogAnd c p)", Type::Int),
+            t("(Cond i (UInt c) u)", Type::UInt),
+            t("(Cond i 0 p)", Type::pointer_to(Type::Int)),
+            t("(Comma c p)", Type::pointer_to(Type::Int)),
+        ]);
+
+        // `x op= y` evaluates `x` once through a hidden pointer variable
+        let main = check_main("char c; c += 1; c--;");
+        let exprs: Vec<String> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some(sexp(expr)),
+            _ => None,
+        }).collect();
+        assert_eq!(exprs, vec![
+            "(Comma (Assign  (Addr c)) (Assign (Deref ) (Char (Add (Int (Deref )) 1))))",
+            "(Char (Add (Int (Comma (Assign  (Addr c)) (Assign (Deref ) (Char (Sub (Int (Deref )) 1))))) 1))",
+        ]);
+        let locals: Vec<(&str, usize)> = main.locals.iter().map(|var| (&var.name as &str, var.offset)).collect();
+        assert_eq!(locals, vec![("c", 1), ("", 16), ("", 24)]);
+
+        let program = check("int a = 7 % 3 | 8; int b = -1 >> 1 ^ 1 << 3; unsigned c = (unsigned)-8 >> 1; \
+            int d = !2 || ~0 && 3; int e = 0 ? 1 : 2;");
+        let data: Vec<Option<Vec<Data>>> = program.globals.iter().map(|global| global.data.clone()).collect();
+        let bytes = |n: i64| Some(vec![Data::Bytes(n.to_le_bytes()[..4].to_vec())]);
+        assert_eq!(data, vec![bytes(9), bytes(-9), bytes(0x7fff_fffc), bytes(1), bytes(2)]);
+    }
+
+    #[test]
+    fn test_struct() {
+        let main = check_main("struct {char a; int b; struct {short c;} d[2];} x, *p; x.a; x.b; x.d; x.d[1].c; p->b; &p->d; x = *p;");
+        let types: Vec<Type> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(expr) => Some(expr.ty().clone()),
+            _ => None,
+        }).collect();
+        let x = main.locals[0].ty.clone();
+        let d = match &x {
+            Type::Struct(ty) => ty.member("d").unwrap().ty.clone(),
+            _ => panic!("expected a struct"),
+        };
+        assert_eq!(types, vec![
+            Type::Char, Type::Int, d.clone(), Type::Short, Type::Int, Type::pointer_to(d), x.clone(),
+        ]);
+        let offsets: Vec<usize> = main.body.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(Expr {kind: ExprKind::Member {member: Some(member), ..}, ..}) => Some(member.offset),
+            _ => None,
+        }).collect();
+        assert_eq!(offsets, vec![0, 4, 8, 0, 4]);
+        assert_eq!(x.size(), 12);
+        assert_eq!(main.locals[0].offset, 12);
+
+        // structs are zero-filled like arrays, with padding between members
+        let program = check("struct {char a; int b; char c;} s = {1, 2}; union {int a; char b;} u = {3}; \
+            struct {int a[2]; char *p;} t = {{4}};");
+        let bytes = |n: i64, size: usize| Data::Bytes(n.to_le_bytes()[..size].to_vec());
+        let zero = |size: usize| Data::Bytes(vec![0; size]);
+        assert_eq!(program.globals[0].data, Some(vec![bytes(1, 1), zero(3), bytes(2, 4), zero(4)]));
+        assert_eq!(program.globals[1].data, Some(vec![bytes(3, 4)]));
+        assert_eq!(program.globals[2].data, Some(vec![bytes(4, 4), zero(4), zero(8)]));
+    }
+
+    #[test]
+    fn test_global() {
+        let program = check(r#"int x = 2 * 3 - 1; char c = -1; int *p = &x; char *s = "ab"; int a[3] = {1, 2}; int *q = a + 2; char t[4] = "abc"; int u; int main() { int x; return x + u; }"#);
+        let data: Vec<Option<Vec<Data>>> = program.globals.iter().map(|global| global.data.clone()).collect();
+        let bytes = |n: i64, size: usize| Data::Bytes(n.to_le_bytes()[..size].to_vec());
+        assert_eq!(data, vec![
+            Some(vec![bytes(5, 4)]),
+            Some(vec![bytes(-1, 1)]),
+            Some(vec![Data::Addr(Symbol::Global("x".to_string()), 0)]),
+            Some(vec![Data::Addr(Symbol::Str(b"ab".to_vec()), 0)]),
+            Some(vec![bytes(1, 4), bytes(2, 4), Data::Bytes(vec![0; 4])]),
+            Some(vec![Data::Addr(Symbol::Global("a".to_string()), 8)]),
+            Some(vec![Data::Bytes(b"abc\0".to_vec())]),
+            None,
+        ]);
+
+        // local variables shadow global ones
+        let main = &program.functions[0];
+        let vars: Vec<&Var> = match &main.body[1].kind {
+            StmtKind::Return(Expr {kind: ExprKind::Binary(_, lhs, rhs), ..}) => {
+                match (&lhs.kind, &rhs.kind) {
+                    (ExprKind::Var {var: Some(x), ..}, ExprKind::Var {var: Some(u), ..}) => vec![x, u],
+                    _ => panic!("expected variables"),
+                }
+            },
+            _ => panic!("expected `return x + u`"),
+        };
+        assert!(matches!(vars[0], Var::Local(_)));
+        assert_eq!(vars[1], &Var::Global(GVar {name: "u".to_string(), ty: Type::Int}));
+
+        // nested arrays and strings with omitted NULs
+        let program = check(r#"int a[2][2] = {{1}, {2, 3}}; char s[2] = "ab";"#);
+        assert_eq!(program.globals[0].data, Some(vec![
+            bytes(1, 4), Data::Bytes(vec![0; 4]), bytes(2, 4), bytes(3, 4),
+        ]));
+        assert_eq!(program.globals[1].data, Some(vec![Data::Bytes(b"ab".to_vec())]));
+    }
+
+    #[test]
+    fn test_error() {
+        assert_eq!(error("int f(int n) { va_list ap; va_start(ap, n); }"),
+            (1, 28, "`va_start` used in a function with fixed parameters".to_string()));
+        assert_eq!(error("int f(int n, ...) { int *ap; va_start(ap, n); }"), (1, 39, "expected a `va_list`".to_string()));
+        assert_eq!(error("int f(va_list ap) { struct {int a;} x; x = va_arg(ap, struct {int a;}); }"),
+            (1, 44, "unsupported type for `va_arg`".to_string()));
+        assert_eq!(error("int f(int a); int main() { f(); }"), (1, 28, "wrong number of arguments to `f`".to_string()));
+        assert_eq!(error("int f(int a, ...); int main() { f(); }"), (1, 33, "wrong number of arguments to `f`".to_string()));
+        assert_eq!(error("int f(int a); int main() { f(1, 2); }"), (1, 28, "wrong number of arguments to `f`".to_string()));
+        assert_eq!(error("int f(int a); long f(int a);"), (1, 15, "conflicting types for `f`".to_string()));
+        assert_eq!(error("int f(int a); int f(int a, ...);"), (1, 15, "conflicting types for `f`".to_string()));
+        assert_eq!(error("int f(); int f() {} int f() {}"), (1, 21, "redefinition of `f`".to_string()));
+        assert_eq!(error("int f(); int f;"), (1, 14, "redefinition of `f`".to_string()));
+        assert_eq!(error("struct s {int a;} f();"), (1, 1, "returning a struct is not supported".to_string()));
+        assert_eq!(error("int main() { int *p; p % 2; }"), (1, 22, "invalid operands to `%`".to_string()));
+        assert_eq!(error("int main() { int *p; 1 << p; }"), (1, 22, "invalid operands to `<<`".to_string()));
+        assert_eq!(error("int main() { int *p; ~p; }"), (1, 22, "invalid operand to `~`".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; !x; }"), (1, 33, "invalid operand to `!`".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; 1 && x; }"), (1, 33, "invalid operands to `&&`".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; x ? 1 : 2; }"), (1, 33, "invalid condition".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; if (x) return 1; }"), (1, 37, "invalid condition".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; while (x) {} }"), (1, 40, "invalid condition".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; for (; x;) {} }"), (1, 40, "invalid condition".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; 1 ? x : 2; }"),
+            (1, 33, "type mismatch in conditional expression".to_string()));
+        assert_eq!(error("int main() { int a[2]; a += 1; }"), (1, 24, "not an lvalue".to_string()));
+        assert_eq!(error("int main() { int *p; p *= 2; }"), (1, 22, "invalid operands to `*`".to_string()));
+        assert_eq!(error("int main() { a = 1; }"), (1, 14, "undeclared variable `a`".to_string()));
+        assert_eq!(error("int main() { { int a; } return a; }"), (1, 32, "undeclared variable `a`".to_string()));
+        assert_eq!(error("int main() { int a = a + b; }"), (1, 26, "undeclared variable `b`".to_string()));
+        assert_eq!(error("int main() { int a; int *a; }"), (1, 25, "redefinition of `a`".to_string()));
+        assert_eq!(error("int f(int a, int a) { }"), (1, 14, "redefinition of `a`".to_string()));
+        assert_eq!(error("int main() { int a; *a; }"), (1, 21, "invalid pointer dereference".to_string()));
+        assert_eq!(error("int main() { int *p; p + p; }"), (1, 22, "invalid operands to `+`".to_string()));
+        assert_eq!(error("int main() { int *p; 1 - p; }"), (1, 22, "invalid operands to `-`".to_string()));
+        assert_eq!(error("int main() { int a[2]; int *p; a = p; }"), (1, 32, "not an lvalue".to_string()));
+        assert_eq!(error("int main() { int a[2] = 1; }"), (1, 18, "array initializer is not supported".to_string()));
+        assert_eq!(error("int main() { return sizeof x; }"), (1, 28, "undeclared variable `x`".to_string()));
+        assert_eq!(error(r#"int main() { "abc" = 0; }"#), (1, 14, "not an lvalue".to_string()));
+        assert_eq!(error("int x; int main() {} char x;"), (1, 27, "redefinition of `x`".to_string()));
+        assert_eq!(error("int main; int main() {}"), (1, 5, "redefinition of `main`".to_string()));
+        assert_eq!(error("int f() {} int f() {}"), (1, 12, "redefinition of `f`".to_string()));
+        assert_eq!(error("int x = y;"), (1, 9, "undeclared variable `y`".to_string()));
+        assert_eq!(error("int y; int x = y + 1;"), (1, 16, "initializer element is not constant".to_string()));
+        assert_eq!(error("int x = f();"), (1, 9, "initializer element is not constant".to_string()));
+        assert_eq!(error("int y; char c = &y;"), (1, 17, "initializer element is not constant".to_string()));
+        assert_eq!(error("int x = 1 / 0;"), (1, 9, "division by zero".to_string()));
+        assert_eq!(error("int a[2] = {1, 2, 3};"), (1, 12, "excess elements in array initializer".to_string()));
+        assert_eq!(error(r#"char s[2] = "abc";"#), (1, 13, "initializer string is too long".to_string()));
+        assert_eq!(error("int a[2] = 1;"), (1, 12, "invalid initializer".to_string()));
+        assert_eq!(error("int x = {1};"), (1, 9, "invalid initializer".to_string()));
+        assert_eq!(error("int main() { int a; a.x; }"), (1, 21, "member reference to a non-struct value".to_string()));
+        assert_eq!(error("int main() { struct {int a;} s; s.b; }"), (1, 33, "no member named `b`".to_string()));
+        assert_eq!(error("int main() { struct s *p; p->a; }"), (1, 27, "member reference to an incomplete type".to_string()));
+        assert_eq!(error("int main() { struct s x; }"), (1, 23, "variable `x` has an incomplete type".to_string()));
+        assert_eq!(error("struct s x;"), (1, 10, "variable `x` has an incomplete type".to_string()));
+        assert_eq!(error("int main() { char a[2000000000]; char b[2000000000]; }"), (1, 39, "stack frame is too large".to_string()));
+        assert_eq!(error("struct s *p, *q; int main() { p - q; }"), (1, 31, "incomplete type".to_string()));
+        assert_eq!(error("struct s *p; int main() { p + 1; }"), (1, 27, "incomplete type".to_string()));
+        assert_eq!(error("struct s *p; int main() { 1 + p; }"), (1, 27, "incomplete type".to_string()));
+        assert_eq!(error("struct s *p; int main() { p++; }"), (1, 27, "incomplete type".to_string()));
+        assert_eq!(error("struct s *p; int main() { sizeof(*p); }"), (1, 27, "incomplete type".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; int y = x; }"), (1, 41, "incompatible types".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x, y; struct {int a;} z; x = y; x = z; }"), (1, 66, "incompatible types".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; x + 1; }"), (1, 33, "invalid operands to `+`".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; int *p; p + x; }"), (1, 41, "invalid operands to `+`".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; int *p; x + p; }"), (1, 41, "invalid operands to `+`".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; int *p; p - x; }"), (1, 41, "invalid operands to `-`".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; int *p; p += x; }"), (1, 41, "invalid operands to `+`".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; x == x; }"), (1, 33, "invalid operands to `==`".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; (long)x; }"), (1, 33, "invalid cast".to_string()));
+        assert_eq!(error("int main() { struct {int a;} x; f(x); }"), (1, 35, "passing a struct is not supported".to_string()));
+        assert_eq!(error("struct s {int a;}; int f(struct s x) {}"), (1, 26, "struct parameter is not supported".to_string()));
+        assert_eq!(error("struct s {int a;} f() {}"), (1, 1, "returning a struct is not supported".to_string()));
+        assert_eq!(error("struct {char a;} s = {1, 2};"), (1, 22, "excess elements in struct initializer".to_string()));
+    }
+
+    fn check(s: &str) -> Program {
+        let mut sources = SourceMap::new();
+        let file = sources.add(None, s.to_string());
+        let tokens = sources.tokenize(file).unwrap();
+        let program = Parser::new(&sources, tokens).parse().unwrap();
+        Sema::new(&sources).check(program).unwrap()
+    }
+
+    // check `s` as the body of `main`
+    fn check_main(s: &str) -> Function {
+        check(&format!("int main() {{ {} }}", s)).functions.pop().unwrap()
+    }
+
+    // (line, column, message) of the semantic error
+    fn error(s: &str) -> (usize, usize, String) {
+        let mut sources = SourceMap::new();
+        let file = sources.add(None, s.to_string());
+        let tokens = sources.tokenize(file).unwrap();
+        let program = Parser::new(&sources, tokens).parse().unwrap();
+        let err = Sema::new(&sources).check(program).unwrap_err();
+        let loc = err.location().unwrap();
+        (loc.line, loc.column, err.message().to_string())
+    }
+
+    fn sexp(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Num(n) => n.to_string(),
+            ExprKind::Var {name, ..} => name.clone(),
+            ExprKind::Binary(op, lhs, rhs) => format!("({:?} {} {})", op, sexp(lhs), sexp(rhs)),
+            ExprKind::Cast(operand) => format!("({:?} {})", expr.ty(), sexp(operand)),
+            ExprKind::Unary(op, operand) => format!("({:?} {})", op, sexp(operand)),
+            ExprKind::Assign(lhs, rhs) => format!("(Assign {} {})", sexp(lhs), sexp(rhs)),
+            ExprKind::Comma(lhs, rhs) => format!("(Comma {} {})", sexp(lhs), sexp(rhs)),
+            ExprKind::Cond {cond, then, els} => format!("(Cond {} {} {})", sexp(cond), sexp(then), sexp(els)),
+            ExprKind::FuncCall {name, args} => {
+                let args: Vec<String> = args.iter().map(sexp).collect();
+                format!("({} {})", name, args.join(" "))
+            },
+            _ => format!("{:?}", expr.kind),
+        }
+    }
+}
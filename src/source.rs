@@ -0,0 +1,97 @@
+use crate::error::CompileError;
+use crate::lexer::{Lexer, Span, Token};
+
+struct SourceFile {
+    // `None` for a program given as a string
+    name: Option<String>,
+    contents: String,
+    // offsets of the starts of the lines
+    line_starts: Vec<usize>,
+}
+
+// SourceMap owns the source files of a compilation.
+// Spans refer to their files by the index in it.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // add a file and return its id
+    pub fn add(&mut self, name: Option<String>, contents: String) -> usize {
+        let line_starts = std::iter::once(0).chain(contents.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        self.files.push(SourceFile {name, contents, line_starts});
+        self.files.len() - 1
+    }
+
+    pub fn name(&self, file: usize) -> Option<&str> {
+        self.files[file].name.as_deref()
+    }
+
+    pub fn contents(&self, file: usize) -> &str {
+        &self.files[file].contents
+    }
+
+    // line number of the start of `span`
+    pub fn line(&self, span: Span) -> usize {
+        self.line_index(span) + 1
+    }
+
+    // column of the start of `span`, counted in bytes from 1
+    pub fn column(&self, span: Span) -> usize {
+        span.start - self.files[span.file].line_starts[self.line_index(span)] + 1
+    }
+
+    fn line_index(&self, span: Span) -> usize {
+        self.files[span.file].line_starts.partition_point(|&start| start <= span.start) - 1
+    }
+
+    // error at `span`, in the file of it
+    pub fn error_at(&self, span: Span, message: &str) -> CompileError {
+        let err = CompileError::at(self.contents(span.file), span.start, message);
+        self.in_file(err, span.file)
+    }
+
+    // tokens of the file `file`
+    pub fn tokenize(&self, file: usize) -> Result<Vec<Token>, CompileError> {
+        Lexer::for_file(self.contents(file), file).tokenize()
+            .map_err(|err| self.in_file(err, file))
+    }
+
+    fn in_file(&self, err: CompileError, file: usize) -> CompileError {
+        match self.name(file) {
+            Some(name) => err.in_file(name),
+            None => err,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_map() {
+        let mut sources = SourceMap::new();
+        let main = sources.add(None, "int x;\nint y;".to_string());
+        let header = sources.add(Some("a.h".to_string()), "\n\nint z;".to_string());
+        assert_eq!((main, header), (0, 1));
+        assert_eq!(sources.name(header), Some("a.h"));
+
+        let tokens = sources.tokenize(header).unwrap();
+        assert_eq!(tokens[1].span(), Span {file: 1, start: 6, end: 7});
+        assert_eq!(sources.line(tokens[1].span()), 3);
+        assert_eq!(sources.column(tokens[1].span()), 5);
+        assert_eq!((sources.line(tokens[0].span()), sources.column(tokens[0].span())), (3, 1));
+
+        let err = sources.error_at(Span {file: 0, start: 7, end: 10}, "oops");
+        assert_eq!(err.to_string(), "2: int y;\n   ^ oops");
+        let bad = sources.add(Some("b.h".to_string()), "int z $".to_string());
+        let err = sources.tokenize(bad).unwrap_err();
+        assert_eq!(err.to_string(), "b.h:1: int z $\n             ^ invalid token");
+    }
+}
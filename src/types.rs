@@ -0,0 +1,413 @@
+use std::cell::OnceCell;
+use std::fmt;
+use std::rc::{Rc, Weak};
+use crate::utils::align_to;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    // `char` is signed
+    Char,
+    Short,
+    Int,
+    Long,
+    UChar,
+    UShort,
+    UInt,
+    ULong,
+    Ptr(Box<Type>),
+    // `base[len]`
+    Array(Box<Type>, usize),
+    // struct or union
+    Struct(StructType),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructKind {
+    Struct,
+    Union,
+}
+
+// Member of a struct or a union, placed at `offset` bytes from the start
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub name: String,
+    pub ty: Type,
+    pub offset: usize,
+}
+
+// Struct or union type, shared by all the uses of one declaration and compared by identity.
+// A type declared before its definition, like `struct node;`, is incomplete until `define`d,
+// so that its members can refer to it like `struct node { struct node *next; }`.
+// The members hold struct types weakly, since such a cycle of `Rc`s would never be freed,
+// so every definition must be kept alive elsewhere, by `Program::structs`.
+#[derive(Clone)]
+pub struct StructType(Handle);
+
+#[derive(Clone)]
+enum Handle {
+    Strong(Rc<StructDef>),
+    // only in the members of a `Layout`
+    Weak(Weak<StructDef>),
+}
+
+struct StructDef {
+    kind: StructKind,
+    tag: Option<String>,
+    layout: OnceCell<Layout>,
+}
+
+struct Layout {
+    members: Vec<Member>,
+    size: usize,
+    align: usize,
+}
+
+impl StructType {
+    // incomplete type, `tag` is `None` if anonymous
+    pub fn new(kind: StructKind, tag: Option<String>) -> Self {
+        Self(Handle::Strong(Rc::new(StructDef {kind, tag, layout: OnceCell::new()})))
+    }
+
+    fn def(&self) -> &StructDef {
+        match &self.0 {
+            Handle::Strong(def) => def,
+            Handle::Weak(_) => unreachable!("weak struct type out of its layout"),
+        }
+    }
+
+    pub fn kind(&self) -> StructKind {
+        self.def().kind
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        self.def().tag.as_deref()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.def().layout.get().is_some()
+    }
+
+    // Complete the type with `members` of (name, type).
+    // Struct members are laid out in order with padding for their alignment,
+    // and union members overlap at offset 0.
+    pub fn define(&self, members: Vec<(String, Type)>) {
+        let mut offset = 0;
+        let mut size = 0;
+        let mut align = 1;
+        let members = members.into_iter().map(|(name, ty)| {
+            if self.kind() == StructKind::Struct {
+                offset = align_to(offset, ty.align());
+            }
+            let member = Member {name, offset, ty: ty.with_handles(&StructType::downgrade)};
+            if self.kind() == StructKind::Struct {
+                offset += ty.size();
+            }
+            size = size.max(member.offset + ty.size());
+            align = align.max(ty.align());
+            member
+        }).collect();
+        let layout = Layout {members, size: align_to(size, align), align};
+        assert!(self.def().layout.set(layout).is_ok(), "struct is already defined");
+    }
+
+    // members in the declaration order, none if incomplete
+    pub fn members(&self) -> Vec<Member> {
+        self.def().layout.get().map_or(Vec::new(), |layout| {
+            layout.members.iter().map(Member::upgrade).collect()
+        })
+    }
+
+    pub fn member(&self, name: &str) -> Option<Member> {
+        let layout = self.def().layout.get()?;
+        layout.members.iter().find(|member| member.name == name).map(Member::upgrade)
+    }
+
+    pub fn size(&self) -> usize {
+        self.def().layout.get().map_or(0, |layout| layout.size)
+    }
+
+    pub fn align(&self) -> usize {
+        self.def().layout.get().map_or(1, |layout| layout.align)
+    }
+
+    fn downgrade(&self) -> Self {
+        match &self.0 {
+            Handle::Strong(def) => Self(Handle::Weak(Rc::downgrade(def))),
+            Handle::Weak(_) => self.clone(),
+        }
+    }
+
+    fn upgrade(&self) -> Self {
+        match &self.0 {
+            Handle::Strong(_) => self.clone(),
+            Handle::Weak(def) => Self(Handle::Strong(def.upgrade().expect("struct type used after its program"))),
+        }
+    }
+
+    fn as_ptr(&self) -> *const StructDef {
+        match &self.0 {
+            Handle::Strong(def) => Rc::as_ptr(def),
+            Handle::Weak(def) => def.as_ptr(),
+        }
+    }
+}
+
+impl Member {
+    fn upgrade(&self) -> Self {
+        Member {ty: self.ty.with_handles(&StructType::upgrade), ..self.clone()}
+    }
+}
+
+impl PartialEq for StructType {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.as_ptr(), other.as_ptr())
+    }
+}
+
+impl Eq for StructType {}
+
+// members are not printed, since they may refer to the type itself
+impl fmt::Debug for StructType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind() {
+            StructKind::Struct => "struct",
+            StructKind::Union => "union",
+        };
+        write!(f, "{} {}", kind, self.tag().unwrap_or("<anonymous>"))
+    }
+}
+
+impl Type {
+    pub fn pointer_to(base: Type) -> Self {
+        Type::Ptr(Box::new(base))
+    }
+
+    pub fn array_of(base: Type, len: usize) -> Self {
+        Type::Array(Box::new(base), len)
+    }
+
+    // `va_list` of System V ABI, an array of one struct:
+    // `struct { unsigned gp_offset; unsigned fp_offset; char *overflow_arg_area; char *reg_save_area; }`
+    pub fn va_list() -> Self {
+        thread_local! {
+            // one struct type for all `va_list`s, since struct types are compared by identity
+            static VA_LIST: StructType = {
+                let ty = StructType::new(StructKind::Struct, Some("__va_list".to_string()));
+                ty.define(vec![
+                    ("gp_offset".to_string(), Type::UInt),
+                    ("fp_offset".to_string(), Type::UInt),
+                    ("overflow_arg_area".to_string(), Type::pointer_to(Type::Char)),
+                    ("reg_save_area".to_string(), Type::pointer_to(Type::Char)),
+                ]);
+                ty
+            };
+        }
+        Type::array_of(Type::Struct(VA_LIST.with(Clone::clone)), 1)
+    }
+
+    // `self` with `f` applied to the struct types in it
+    fn with_handles(&self, f: &dyn Fn(&StructType) -> StructType) -> Type {
+        match self {
+            Type::Ptr(base) => Type::Ptr(Box::new(base.with_handles(f))),
+            Type::Array(base, len) => Type::Array(Box::new(base.with_handles(f)), *len),
+            Type::Struct(ty) => Type::Struct(f(ty)),
+            ty => ty.clone(),
+        }
+    }
+
+    // whether `self` is a `va_list` or a pointer to its element, which a `va_list` parameter is
+    pub fn is_va_list(&self) -> bool {
+        let va_list = Type::va_list();
+        match self {
+            Type::Ptr(base) => Some(&**base) == va_list.base(),
+            ty => *ty == va_list,
+        }
+    }
+
+    // the type pointed to, if `self` is a pointer or an array,
+    // which decays to a pointer to its first element
+    pub fn base(&self) -> Option<&Type> {
+        match self {
+            Type::Ptr(base) | Type::Array(base, _) => Some(base),
+            _ => None,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            Type::Char | Type::Short | Type::Int | Type::Long
+                | Type::UChar | Type::UShort | Type::UInt | Type::ULong
+        )
+    }
+
+    pub fn is_unsigned(&self) -> bool {
+        matches!(self, Type::UChar | Type::UShort | Type::UInt | Type::ULong)
+    }
+
+    // the unsigned integer type of the same size
+    pub fn to_unsigned(&self) -> Self {
+        match self {
+            Type::Char => Type::UChar,
+            Type::Short => Type::UShort,
+            Type::Int => Type::UInt,
+            Type::Long => Type::ULong,
+            ty => ty.clone(),
+        }
+    }
+
+    pub fn is_pointer(&self) -> bool {
+        matches!(self, Type::Ptr(_))
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self, Type::Array(..))
+    }
+
+    // struct or union
+    pub fn is_struct(&self) -> bool {
+        matches!(self, Type::Struct(_))
+    }
+
+    // whether the size is unknown, like a struct declared but not defined
+    pub fn is_incomplete(&self) -> bool {
+        match self {
+            Type::Struct(ty) => !ty.is_complete(),
+            Type::Array(base, _) => base.is_incomplete(),
+            _ => false,
+        }
+    }
+
+    // bytes of a value of the type
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Char | Type::UChar => 1,
+            Type::Short | Type::UShort => 2,
+            Type::Int | Type::UInt => 4,
+            Type::Long | Type::ULong | Type::Ptr(_) => 8,
+            Type::Array(base, len) => base.size() * len,
+            Type::Struct(ty) => ty.size(),
+        }
+    }
+
+    // alignment in bytes
+    pub fn align(&self) -> usize {
+        match self {
+            Type::Array(base, _) => base.align(),
+            Type::Struct(ty) => ty.align(),
+            _ => self.size(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pointer() {
+        let pp = Type::pointer_to(Type::pointer_to(Type::Int));
+        assert!(pp.is_pointer());
+        assert_eq!(pp.base(), Some(&Type::pointer_to(Type::Int)));
+        assert_eq!(pp.base().and_then(Type::base), Some(&Type::Int));
+        assert!(!Type::Int.is_pointer());
+        assert_eq!(Type::Int.base(), None);
+        assert_eq!(pp.size(), 8);
+    }
+
+    #[test]
+    fn array() {
+        // int a[2][3]
+        let a = Type::array_of(Type::array_of(Type::Int, 3), 2);
+        assert!(a.is_array());
+        assert!(!a.is_pointer());
+        assert_eq!(a.size(), 24);
+        assert_eq!(a.base().map(Type::size), Some(12));
+        assert_eq!(Type::array_of(Type::pointer_to(Type::Int), 0).size(), 0);
+    }
+
+    #[test]
+    fn char() {
+        let s = Type::array_of(Type::Char, 5);
+        assert_eq!((Type::Char.size(), Type::Char.align()), (1, 1));
+        assert_eq!((s.size(), s.align()), (5, 1));
+        assert_eq!(Type::array_of(Type::Int, 2).align(), 4);
+        assert_eq!(Type::pointer_to(Type::Char).size(), 8);
+    }
+
+    #[test]
+    fn va_list() {
+        let ty = Type::va_list();
+        assert_eq!((ty.size(), ty.align()), (24, 8));
+        assert_eq!(ty, Type::va_list());
+        assert!(ty.is_va_list());
+        assert!(Type::pointer_to(ty.base().unwrap().clone()).is_va_list());
+        assert!(!Type::pointer_to(Type::Char).is_va_list());
+        match ty.base() {
+            Some(Type::Struct(st)) => {
+                let offsets: Vec<usize> = st.members().iter().map(|member| member.offset).collect();
+                assert_eq!(offsets, vec![0, 4, 8, 16]);
+            },
+            _ => panic!("expected an array of a struct"),
+        }
+    }
+
+    #[test]
+    fn structure() {
+        // struct { char a; int b; char c[3]; long d; }
+        let ty = StructType::new(StructKind::Struct, None);
+        assert!(!ty.is_complete());
+        ty.define(vec![
+            ("a".to_string(), Type::Char),
+            ("b".to_string(), Type::Int),
+            ("c".to_string(), Type::array_of(Type::Char, 3)),
+            ("d".to_string(), Type::Long),
+        ]);
+        let offsets: Vec<usize> = ty.members().iter().map(|member| member.offset).collect();
+        assert_eq!(offsets, vec![0, 4, 8, 16]);
+        assert_eq!((ty.size(), ty.align()), (24, 8));
+        assert_eq!(ty.member("c").map(|member| member.ty), Some(Type::array_of(Type::Char, 3)));
+        assert_eq!(ty.member("e"), None);
+
+        // union { char a[5]; short b; }
+        let ty = StructType::new(StructKind::Union, Some("u".to_string()));
+        ty.define(vec![("a".to_string(), Type::array_of(Type::Char, 5)), ("b".to_string(), Type::Short)]);
+        assert!(ty.members().iter().all(|member| member.offset == 0));
+        assert_eq!((ty.size(), ty.align()), (6, 2));
+        assert_eq!(format!("{:?}", Type::Struct(ty.clone())), "Struct(union u)");
+
+        // types are compared by identity
+        let node = StructType::new(StructKind::Struct, Some("node".to_string()));
+        let ty = Type::Struct(node.clone());
+        assert!(ty.is_incomplete());
+        node.define(vec![("next".to_string(), Type::pointer_to(ty.clone()))]);
+        assert!(!ty.is_incomplete());
+        assert_eq!(ty.size(), 8);
+        assert_eq!(node.members()[0].ty, Type::pointer_to(ty.clone()));
+        assert_ne!(ty, Type::Struct(StructType::new(StructKind::Struct, Some("node".to_string()))));
+
+        // the member doesn't keep the type itself alive
+        let def = match &node.0 {
+            Handle::Strong(def) => Rc::downgrade(def),
+            Handle::Weak(_) => unreachable!(),
+        };
+        drop((node, ty));
+        assert!(def.upgrade().is_none());
+    }
+
+    #[test]
+    fn integer() {
+        let sizes: Vec<usize> = [Type::Char, Type::Short, Type::Int, Type::Long].iter()
+            .map(|ty| ty.size())
+            .collect();
+        assert_eq!(sizes, vec![1, 2, 4, 8]);
+        assert_eq!(Type::Short.to_unsigned(), Type::UShort);
+        assert_eq!(Type::UInt.size(), 4);
+        assert_eq!(Type::ULong.align(), 8);
+        assert!(Type::UChar.is_unsigned());
+        assert!(!Type::Char.is_unsigned());
+        assert!(Type::ULong.is_integer());
+        assert!(!Type::pointer_to(Type::Int).is_integer());
+        assert!(!Type::pointer_to(Type::UInt).is_unsigned());
+    }
+}
@@ -7,6 +7,11 @@
 pub struct Consumer {
     queue: Vec<char>,
     pos: usize,
+    // byte offset of `pos` in the original string
+    offset: usize,
+    // 1-origin line and column (in chars) of `pos`
+    line: usize,
+    column: usize,
 }
 
 
@@ -16,30 +21,31 @@ impl Consumer {
         Self {
             queue: vec,
             pos: 0,
+            offset: 0,
+            line: 1,
+            column: 1,
         }
     }
-    
-    // Return next char as `String`.
-    pub fn next(&mut self) -> Option<String> {
-        if let Some(c) = self.next_char() {
-            Some(c.to_string())
-        } else {
-            None
-        }
-    }
-    
+
     // Inner function for `next` and `next_n`
     // Return next char if `self.queue` has the next element,
-    fn next_char(&mut self) -> Option<char> {
+    pub fn next_char(&mut self) -> Option<char> {
         if self.pos < self.queue.len() {
             let res = self.queue[self.pos];
             self.pos +=1;
+            self.offset += res.len_utf8();
+            if res == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
             Some(res)
         } else {
             None
         }
     }
-    
+
     // return next n chars as `String`
     pub fn next_n(&mut self, n: usize) -> Option<String> {
         let mut vec = Vec::new();
@@ -54,53 +60,35 @@ impl Consumer {
         Some(res)
 
     }
-    
+
     // return string from `self.pos` to next white space
     pub fn next_until_space(&mut self) -> Option<String> {
+        Some(self.next_while(|c| !is_space(c)))
+    }
+
+    // return string from `self.pos` while `f` holds for each char
+    pub fn next_while<F: Fn(char) -> bool>(&mut self, f: F) -> String {
         let mut vec = Vec::new();
-        loop {
-            match self.peek_char() {
-                Some(c) => {
-                    match c {
-                        ' ' | '\t' => {
-                            break;
-                        }
-                        _ => {
-                            self.next();
-                            vec.push(c)
-                        }
-                    }
-                },
-                None => {
-                    break;
-                }
+        while let Some(c) = self.peek_char() {
+            if !f(c) {
+                break;
             }
+            self.next_char();
+            vec.push(c);
         }
-        let res = vec.into_iter().collect::<String>();
-        Some(res)
+        vec.into_iter().collect::<String>()
     }
-    
+
     // return a char as `String`
     pub fn peek(&self) -> Option<String> {
-        if let Some(c) = self.peek_char() {
-            Some(c.to_string())
-        } else {
-            None
-        }
-
+        self.peek_char().map(|c| c.to_string())
     }
-    
+
     // inner function for mainly `peek` and `peek_n`
-    fn peek_char(&self) -> Option<char> {
-        let res: char;
-        if self.pos < self.queue.len() {
-            res = self.queue[self.pos];
-            Some(res)
-        } else {
-            None
-        }
+    pub fn peek_char(&self) -> Option<char> {
+        self.queue.get(self.pos).copied()
     }
-    
+
     // return chars as `String`
     pub fn peek_n(&self, n: usize) -> Option<String> {
         let mut vec = Vec::new();
@@ -114,78 +102,61 @@ impl Consumer {
         let res = vec.iter().collect::<String>();
         Some(res)
     }
-    
-    // return `usize` integer
-    pub fn to_usize(&mut self) -> Option<usize> {
-        let mut result: usize = 0;
-
-        // check whether the first char is number
-        match self.peek_char() {
-            Some(c) => {
-                match c {
-                    '0'..='9' => {
-                    }
-                    _ => {
-                        return None;
-                    }
-                }
-            }
-            None => {
-                return None;
-            }
-        }
 
-        loop {
-            match self.peek_char() {
-                Some(c) => {
-                    match c {
-                        '0'..='9' => {
-                            self.next();
-                            let n = c.to_digit(10).unwrap() as usize;
-                            result = result*10 + n;
-                        }
-                        _ => {
-                            break;
-                        }
-                    }
-                }
-                None => {
-                    break;
-                }
-            }
-        }
-        return Some(result);
+    // return byte offset of the next char
+    pub fn offset(&self) -> usize {
+        self.offset
     }
-    
-    // skip white spaces
+
+    // return line number of the next char
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    // return column number of the next char
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    // skip white spaces, including line breaks
     pub fn skip_space(&mut self) {
-        loop {
-            if let Some(c) = self.peek_char() {
-                if " \t".contains(c) {
-                    self.next_char();
-                } else {
-                    break;
-                }
-            } else {
-                break;
-            }
-        }
+        self.next_while(is_space);
     }
 
 }
 
+// space, tab, newline, carriage return, form feed and vertical tab
+pub fn is_space(c: char) -> bool {
+    " \t\n\r\x0c\x0b".contains(c)
+}
+
+// round `n` up to the nearest multiple of `align`
+pub fn align_to(n: usize, align: usize) -> usize {
+    n.div_ceil(align) * align
+}
+
+impl Iterator for Consumer {
+    type Item = String;
+
+    // Return next char as `String`.
+    fn next(&mut self) -> Option<String> {
+        self.next_char().map(|c| c.to_string())
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
     #[test]
     fn consumer() {
         consumer_next();
         consumer_peek();
-        consumer_to_usize();
         consumer_skip_space();
         consumer_next_until_space();
+        consumer_offset();
+        consumer_line_column();
     }
 
     #[test]
@@ -219,17 +190,7 @@ mod tests {
         assert_eq!(con.peek_n(1000), None);
         assert_eq!(con.peek_n(8), Some("o people".to_string()));
     }
-    
-    #[test]
-    fn consumer_to_usize() {
-        let mut con = Consumer::new("12345");
-        assert_eq!(con.to_usize(), Some(12345));
-        let mut con = Consumer::new("a");
-        assert_eq!(con.to_usize(), None);
-        let mut con = Consumer::new("123a");
-        assert_eq!(con.to_usize(), Some(123));
-    }
-    
+
     #[test]
     fn consumer_skip_space() {
         let mut con = Consumer::new("  \t  konnichwassup   ");
@@ -240,7 +201,43 @@ mod tests {
         con.skip_space();
         assert_eq!(con.peek(), None);
 
+        let mut con = Consumer::new(" \n\r\n\x0c\x0b\tx");
+        con.skip_space();
+        assert_eq!(con.peek(), Some("x".to_string()));
+    }
+
+    #[test]
+    fn test_align_to() {
+        assert_eq!(align_to(0, 16), 0);
+        assert_eq!(align_to(1, 16), 16);
+        assert_eq!(align_to(16, 16), 16);
+        assert_eq!(align_to(17, 8), 24);
     }
-}
 
+    #[test]
+    fn consumer_offset() {
+        let mut con = Consumer::new("aあb");
+        assert_eq!(con.offset(), 0);
+        con.next();
+        assert_eq!(con.offset(), 1);
+        con.next();
+        assert_eq!(con.offset(), 4);
+        assert_eq!(con.next_while(char::is_alphabetic), "b");
+        assert_eq!(con.offset(), 5);
+    }
 
+    #[test]
+    fn consumer_line_column() {
+        let mut con = Consumer::new("ab\nあ\n\nc");
+        assert_eq!((con.line(), con.column()), (1, 1));
+        con.next_n(2);
+        assert_eq!((con.line(), con.column()), (1, 3));
+        con.next();
+        assert_eq!((con.line(), con.column()), (2, 1));
+        con.next();
+        assert_eq!((con.line(), con.column()), (2, 2));
+        con.skip_space();
+        assert_eq!((con.line(), con.column()), (4, 1));
+        assert_eq!(con.next(), Some("c".to_string()));
+    }
+}
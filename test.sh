@@ -0,0 +1,513 @@
+#!/bin/bash
+# End-to-end tests: compile each program, run it with cc and check the exit code.
+cargo build -q || exit 1
+compiler=./target/debug/compiler-v1
+
+# helper functions for the compiled programs to call
+cat <<EOF | cc -xc -c -o tmp2.o -
+int ret3() { return 3; }
+int ret5() { return 5; }
+int add(int x, int y) { return x+y; }
+int sub(int x, int y) { return x-y; }
+int add6(int a, int b, int c, int d, int e, int f) {
+  return a+b+c+d+e+f;
+}
+int sub8(int a, int b, int c, int d, int e, int f, int g, int h) {
+  return a-b-c-d-e-f-g-h;
+}
+EOF
+
+assert() {
+  expected="$1"
+  input="$2"
+
+  "$compiler" -o tmp.s "$input" || exit 1
+  cc -z noexecstack -o tmp tmp.s tmp2.o
+  ./tmp
+  actual="$?"
+
+  if [ "$actual" = "$expected" ]; then
+    echo "$input => $actual"
+  else
+    echo "$input => $expected expected, but got $actual"
+    exit 1
+  fi
+}
+
+# assert_error <program>: the compiler must fail with a diagnostic
+assert_error() {
+  input="$1"
+
+  if "$compiler" "$input" > /dev/null 2> tmp.err; then
+    echo "$input => error expected, but compiled"
+    exit 1
+  fi
+  echo "$input => $(tail -n 1 tmp.err | sed 's/^ *//')"
+}
+
+assert 0 'int main() { 0; }'
+assert 42 'int main() { 42; }'
+assert 21 'int main() { 5+20-4; }'
+assert 41 'int main() {  12 + 34 - 5 ; }'
+assert 47 'int main() { 5+6*7; }'
+assert 15 'int main() { 5*(9-6); }'
+assert 4 'int main() { (3+5)/2; }'
+assert 10 'int main() { -10+20; }'
+
+assert 0 'int main() { 0==1; }'
+assert 1 'int main() { 42==42; }'
+assert 1 'int main() { 0!=1; }'
+assert 0 'int main() { 42!=42; }'
+
+assert 1 'int main() { 0<1; }'
+assert 0 'int main() { 1<1; }'
+assert 0 'int main() { 2<1; }'
+assert 1 'int main() { 0<=1; }'
+assert 1 'int main() { 1<=1; }'
+assert 0 'int main() { 2<=1; }'
+
+assert 1 'int main() { 1>0; }'
+assert 0 'int main() { 1>1; }'
+assert 0 'int main() { 1>2; }'
+assert 1 'int main() { 1>=0; }'
+assert 1 'int main() { 1>=1; }'
+assert 0 'int main() { 1>=2; }'
+
+assert 3 'int main() { 1; 2; 3; }'
+assert 2 'int main() { 1 + 2; 4 - 2; }'
+
+assert 3 'int main() { int a; a = 3; a; }'
+assert 3 'int main() { int a = 3; a; }'
+assert 8 'int main() { int a = 3; int z = 5; a + z; }'
+assert 6 'int main() { int a, b; a = b = 3; a + b; }'
+assert 3 'int main() { int foo = 3; foo; }'
+assert 8 'int main() { int foo123 = 3, bar = 5; foo123 + bar; }'
+assert 3 'int main() { int a = 1; int b = a + 2; b; }'
+
+assert 1 'int main() { return 1; 2; 3; }'
+assert 2 'int main() { 1; return 2; 3; }'
+assert 3 'int main() { 1; 2; return 3; }'
+assert 5 'int main() { int returnx = 5; return returnx; }'
+
+assert 3 'int main() { if (0) return 2; return 3; }'
+assert 3 'int main() { if (1-1) return 2; return 3; }'
+assert 2 'int main() { if (1) return 2; return 3; }'
+assert 2 'int main() { if (2-1) return 2; return 3; }'
+assert 4 'int main() { if (0) return 3; else return 4; }'
+assert 3 'int main() { if (1) return 3; else return 4; }'
+assert 5 'int main() { if (0) return 3; else if (0) return 4; else return 5; }'
+assert 4 'int main() { int a = 0; if (a == 0) a = 4; else a = 5; a; }'
+
+assert 10 'int main() { int i = 0; while (i < 10) i = i + 1; return i; }'
+assert 0 'int main() { while (0) return 1; return 0; }'
+
+assert 55 'int main() { int i = 0, j = 0; for (i = 0; i <= 10; i = i + 1) j = i + j; return j; }'
+assert 3 'int main() { for (;;) return 3; return 5; }'
+assert 16 'int main() { int i = 1; for (; i < 10;) i = i * 2; return i; }'
+assert 45 'int main() { int i, j, s = 0; for (i = 0; i < 10; i = i + 1) for (j = 0; j < i; j = j + 1) s = s + 1; return s; }'
+
+assert 3 'int main() { {1; {2;} return 3;} }'
+assert 5 'int main() { { int a = 2; int b = 3; return a + b; } }'
+assert 55 'int main() { int i = 0; int j = 0; while (i <= 10) { j = i + j; i = i + 1; } return j; }'
+assert 10 'int main() { int a = 1; { a = 10; } return a; }'
+assert 1 'int main() { int a = 1; { int a = 10; } return a; }'
+assert 3 'int main() { int b = 3; { int a = 1; } int a = 2; return b; }'
+assert 4 'int main() { {} {{}} return 4; }'
+
+assert 3 'int main() { return ret3(); }'
+assert 5 'int main() { return ret5(); }'
+assert 8 'int main() { return add(3, 5); }'
+assert 2 'int main() { return sub(5, 3); }'
+assert 21 'int main() { return add6(1,2,3,4,5,6); }'
+assert 66 'int main() { return add6(1,2,add6(3,4,5,6,7,8),9,10,11); }'
+assert 136 'int main() { return add6(1,2,add6(3,add6(4,5,6,7,8,9),10,11,12,13),14,15,16); }'
+assert 9 'int main() { int a = 1, b = 2; return add(a, ret3()) + add(b, ret3()); }'
+
+assert 32 'int main() { return ret32(); } int ret32() { return 32; }'
+assert 7 'int main() { return add2(3,4); } int add2(int x, int y) { return x+y; }'
+assert 1 'int main() { return sub2(4,3); } int sub2(int x, int y) { return x-y; }'
+assert 55 'int main() { return fib(9); } int fib(int x) { if (x<=1) return 1; return fib(x-1) + fib(x-2); }'
+assert 21 'int main() { return sum6(1,2,3,4,5,6); } int sum6(int a, int b, int c, int d, int e, int f) { return a+b+c+d+e+f; }'
+
+assert 3 'int main() { int x = 3; return *&x; }'
+assert 3 'int main() { int x = 3; int *y = &x; int **z = &y; return **z; }'
+assert 5 'int main() { int x = 3; int y = 5; return *(&x - 1); }'
+assert 3 'int main() { int x = 3; int y = 5; return *(&y + 1); }'
+assert 3 'int main() { int x = 3; int y = 5; return *(1 + &y); }'
+assert 5 'int main() { int x = 3; int *y = &x; *y = 5; return x; }'
+assert 7 'int main() { int x = 3; int y = 5; *(&x - 1) = 7; return y; }'
+assert 7 'int main() { int x = 3; int y = 5; *(&y + 1) = 7; return x; }'
+assert 8 'int main() { int x = 3; int *p = &x; int **q = &p; **q = 8; return x; }'
+assert 10 'int main() { int x = 10; return *&*&x; }'
+assert 5 'int main() { return - -5; }'
+assert 2 'int main() { int x, y, z; return &x - &z; }'
+assert 4 'int main() { int x = 4; int *p = &x - 3; return *(p + 3); }'
+assert 4 'int main() { int x = 4; return *deref_next(&x - 1); } int *deref_next(int *p) { return p + 1; }'
+
+assert 3 'int main() { int x[2]; int *y = &x; *y = 3; return *x; }'
+assert 3 'int main() { int x[3]; *x = 3; *(x+1) = 4; *(x+2) = 5; return *x; }'
+assert 4 'int main() { int x[3]; *x = 3; *(x+1) = 4; *(x+2) = 5; return *(x+1); }'
+assert 5 'int main() { int x[3]; *x = 3; *(x+1) = 4; *(x+2) = 5; return *(x+2); }'
+assert 0 'int main() { int x[2][3]; int *y = x; *y = 0; return **x; }'
+assert 1 'int main() { int x[2][3]; int *y = x; *(y+1) = 1; return *(*x+1); }'
+assert 5 'int main() { int x[2][3]; int *y = x; *(y+5) = 5; return *(*(x+1)+2); }'
+assert 3 'int main() { int x[3]; *x = 3; x[1] = 4; x[2] = 5; return *x; }'
+assert 5 'int main() { int x[3]; *x = 3; x[1] = 4; 2[x] = 5; return *(x+2); }'
+assert 4 'int main() { int x[2][3]; int *y = x; y[4] = 4; return x[1][1]; }'
+assert 45 'int main() { int a[10]; int i; for (i = 0; i < 10; i = i + 1) a[i] = i; int s = 0; for (i = 0; i < 10; i = i + 1) s = s + a[i]; return s; }'
+assert 6 'int main() { int a[3]; a[0] = 1; a[1] = 2; a[2] = 3; return sum3(a); } int sum3(int a[3]) { return a[0] + a[1] + a[2]; }'
+assert 3 'int main() { int a[4]; return &a[3] - a; }'
+
+assert 4 'int main() { int x; return sizeof(x); }'
+assert 4 'int main() { int x; return sizeof x; }'
+assert 8 'int main() { int *x; return sizeof(x); }'
+assert 16 'int main() { int x[4]; return sizeof(x); }'
+assert 48 'int main() { int x[3][4]; return sizeof(x); }'
+assert 16 'int main() { int x[3][4]; return sizeof(*x); }'
+assert 4 'int main() { int x[3][4]; return sizeof(**x); }'
+assert 5 'int main() { int x[3][4]; return sizeof(**x) + 1; }'
+assert 5 'int main() { int x[3][4]; return sizeof **x + 1; }'
+assert 4 'int main() { int x[3][4]; return sizeof(**x + 1); }'
+assert 4 'int main() { int x = 1; return sizeof(x = 2); }'
+assert 1 'int main() { int x = 1; sizeof(x = 2); return x; }'
+assert 4 'int main() { return sizeof(int); }'
+assert 8 'int main() { return sizeof(int **); }'
+assert 20 'int main() { return sizeof(int[5]); }'
+
+assert 1 'int main() { char x = 1; return x; }'
+assert 1 'int main() { char x = 1; char y = 2; return x; }'
+assert 2 'int main() { char x = 1; char y = 2; return y; }'
+assert 1 'int main() { char x; return sizeof(x); }'
+assert 10 'int main() { char x[10]; return sizeof(x); }'
+assert 1 'int main() { char x = 257; return x; }'
+assert 1 'int main() { return sub_char(7, 3, 3); } int sub_char(char a, char b, char c) { return a-b-c; }'
+assert 6 'int main() { char a[3]; a[0] = 1; a[1] = 2; a[2] = 3; return a[0] + a[1] + a[2]; }'
+assert 3 'int main() { int x = 0; char *p = &x; p[0] = 3; return x; }'
+
+assert 0 'int main() { return ""[0]; }'
+assert 1 'int main() { return sizeof(""); }'
+assert 97 'int main() { return "abc"[0]; }'
+assert 98 'int main() { return "abc"[1]; }'
+assert 99 'int main() { return "abc"[2]; }'
+assert 0 'int main() { return "abc"[3]; }'
+assert 4 'int main() { return sizeof("abc"); }'
+assert 7 'int main() { return "\a"[0]; }'
+assert 8 'int main() { return "\b"[0]; }'
+assert 9 'int main() { return "\t"[0]; }'
+assert 10 'int main() { return "\n"[0]; }'
+assert 11 'int main() { return "\v"[0]; }'
+assert 12 'int main() { return "\f"[0]; }'
+assert 13 'int main() { return "\r"[0]; }'
+assert 27 'int main() { return "\e"[0]; }'
+assert 106 'int main() { return "\j"[0]; }'
+assert 34 'int main() { return "\""[0]; }'
+assert 92 'int main() { return "\\"[0]; }'
+assert 0 'int main() { return "\0"[0]; }'
+assert 16 'int main() { return "\20"[0]; }'
+assert 65 'int main() { return "\101"[0]; }'
+assert 104 'int main() { return "\1500"[0]; }'
+assert 48 'int main() { return "\1500"[1]; }'
+assert 0 'int main() { return "\x00"[0]; }'
+assert 119 'int main() { return "\x77"[0]; }'
+assert 165 'int main() { return "\xA5"[0]; }'
+assert 255 'int main() { return "\x00ff"[0]; }'
+assert 3 'int main() { char *s = "abc"; return s[2] - s[0] + 1; }'
+assert 5 'int main() { return strlen5("hello"); } int strlen5(char *s) { int n = 0; while (s[n]) n = n + 1; return n; }'
+
+assert 97 "int main() { return 'a'; }"
+assert 10 "int main() { return '\\n'; }"
+assert 39 "int main() { return '\\''; }"
+assert 1 "int main() { return '\\xff' == -1; }"
+assert 4 "int main() { return sizeof('a'); }"
+
+assert 0 'int main() { printf("hello, %s %d\n", "world", 42); return 0; }'
+assert 12 'int printf(char *fmt, ...); int main() { return printf("%d %d %d %d %d %d\n", 1, 2, 3, 4, 5, 6); }'
+assert 6 'int sub8(int a, int b, int c, int d, int e, int f, int g, int h); int main() { return sub8(64, 1, 2, 4, 8, 16, 32, -5); }'
+assert 7 'int sub8(int a, int b, int c, int d, int e, int f, int g, int h); int main() { return 1 + sub8(64, 1, 2, 4, 8, 16, 32, -5); }'
+assert 9 'int add6(int a, int b, int c, int d, int e, int f); int main() { char c = 4; return add6(c, 1, 1, 1, 1, 1); }'
+assert 55 'int sum(int n, ...) { va_list ap; va_start(ap, n); int s = 0; while (n--) s += va_arg(ap, int); va_end(ap); return s; } int main() { return sum(10, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10); }'
+assert 43 'int f(int n, ...) { va_list ap, aq; va_start(ap, n); va_copy(aq, ap); va_arg(ap, int); return va_arg(ap, int) * 10 + va_arg(aq, int); } int main() { return f(2, 3, 4); }'
+assert 98 'char g(int n, ...) { va_list ap; va_start(ap, n); va_arg(ap, long); char *s = va_arg(ap, char *); return s[1]; } int main() { return g(2, 1l, "abc"); }'
+assert 3 'int second(va_list ap) { va_arg(ap, int); return va_arg(ap, int); } int f(int n, ...) { va_list ap; va_start(ap, n); return second(ap); } int main() { return f(0, 2, 3); }'
+
+assert 2 'int main() { return sizeof(short); }'
+assert 8 'int main() { return sizeof(long); }'
+assert 8 'int main() { return sizeof(long long int); }'
+assert 4 'int main() { return sizeof(unsigned); }'
+assert 1 'int main() { return sizeof(unsigned char); }'
+assert 24 'int main() { short a[2]; long b[2]; int c; return sizeof(a) + sizeof(b) + sizeof(c); }'
+assert 1 'int main() { short x = 32767; x = x + 1; return x == -32768; }'
+assert 1 'int main() { int x = 2147483647; x = x + 1; return x < 0; }'
+assert 1 'int main() { long x = 2147483647; x = x + 1; return x > 0; }'
+assert 1 'int main() { unsigned x = 0; x = x - 1; return x > 0; }'
+assert 0 'int main() { unsigned char c = 255; c = c + 1; return c; }'
+assert 1 'int main() { char c = 255; return c == -1; }'
+assert 255 'int main() { unsigned char c = -1; return c; }'
+assert 1 'int main() { return (unsigned)-1 / 2 == 2147483647; }'
+assert 0 'int main() { return -1 < (unsigned)1; }'
+assert 1 'int main() { return -1 < (long)(unsigned)1; }'
+assert 44 'int main() { return (char)300; }'
+assert 1 'int main() { return (short)65537; }'
+assert 1 'int main() { long x = 4294967296; return x / 65536 / 65536; }'
+assert 2 'long f(long x) { return x * 2; } int main() { return f(1000000000) / 1000000000; }'
+assert 254 'short f(short a, unsigned char b) { return a + b; } int main() { return f(-1, 255); }'
+assert 3 'int main() { unsigned short s = 65535; int i = s; return i / 21845; }'
+assert 1 'int main() { unsigned long x = -1; return x / 2 > 4611686018427387904; }'
+assert 112 'int g = -200; unsigned char c = 312; int main() { return g + 256 + c; }'
+assert 5 'int a[3]; int main() { return &a[1] - &a[0] + (long)&a[1] - (long)&a[0]; }'
+
+assert 1 'int main() { struct {int a; int b;} x; x.a=1; x.b=2; return x.a; }'
+assert 2 'int main() { struct {int a; int b;} x; x.a=1; x.b=2; return x.b; }'
+assert 1 'int main() { struct {char a; int b; char c;} x; x.a=1; x.b=2; x.c=3; return x.a; }'
+assert 3 'int main() { struct {char a; int b; char c;} x; x.b=1; x.b=2; x.c=3; return x.c; }'
+assert 0 'int main() { struct {char a; char b;} x[3]; char *p=x; p[0]=0; return x[0].a; }'
+assert 1 'int main() { struct {char a; char b;} x[3]; char *p=x; p[1]=1; return x[0].b; }'
+assert 2 'int main() { struct {char a; char b;} x[3]; char *p=x; p[2]=2; return x[1].a; }'
+assert 3 'int main() { struct {char a; char b;} x[3]; char *p=x; p[3]=3; return x[1].b; }'
+assert 6 'int main() { struct {char a[3]; char b[5];} x; char *p=&x; x.a[0]=6; return p[0]; }'
+assert 7 'int main() { struct {char a[3]; char b[5];} x; char *p=&x; x.b[0]=7; return p[3]; }'
+assert 6 'int main() { struct { struct { char b; } a; } x; x.a.b=6; return x.a.b; }'
+assert 4 'int main() { struct {int a;} x; return sizeof(x); }'
+assert 8 'int main() { struct {int a; int b;} x; return sizeof(x); }'
+assert 12 'int main() { struct {int a[3];} x; return sizeof(x); }'
+assert 16 'int main() { struct {int a;} x[4]; return sizeof(x); }'
+assert 24 'int main() { struct {int a[3];} x[2]; return sizeof(x); }'
+assert 2 'int main() { struct {char a; char b;} x; return sizeof(x); }'
+assert 0 'int main() { struct {} x; return sizeof(x); }'
+assert 8 'int main() { struct {char a; int b;} x; return sizeof(x); }'
+assert 8 'int main() { struct {int a; char b;} x; return sizeof(x); }'
+assert 16 'int main() { struct {char a; long b;} x; return sizeof(x); }'
+assert 8 'int main() { struct t {int a; int b;} x; struct t y; return sizeof(y); }'
+assert 8 'int main() { struct t {int a; int b;}; struct t y; return sizeof(y); }'
+assert 2 'int main() { struct t {char a[2];}; { struct t {char a[4];}; } struct t y; return sizeof(y); }'
+assert 3 'int main() { struct t {int x;}; int t=1; struct t y; y.x=2; return t+y.x; }'
+assert 3 'int main() { struct t {char a;} x; struct t *y = &x; x.a=3; return y->a; }'
+assert 3 'int main() { struct t {char a;} x; struct t *y = &x; y->a=3; return x.a; }'
+assert 7 'int main() { struct node {int v; struct node *next;} a, b; a.v = 3; a.next = &b; b.v = 4; b.next = 0; return a.v + a.next->v; }'
+assert 5 'struct a {struct b *p;}; struct b {int v;}; int main() { struct a x; int n[1]; n[0] = 5; x.p = n; return x.p->v; }'
+assert 8 'int main() { union {int a; char b[6];} x; return sizeof(x); }'
+assert 3 'int main() { union {int a; char b[4];} x; x.a = 515; return x.b[0]; }'
+assert 2 'int main() { union {int a; char b[4];} x; x.a = 515; return x.b[1]; }'
+assert 0 'int main() { union {int a; char b[4];} x; x.a = 515; return x.b[2]; }'
+assert 4 'int main() { union {struct {char a; char b;} c; short d;} x; x.d = 1027; return x.c.b; }'
+assert 3 'int main() { struct {int a; int b;} x, y; x.a = 3; y = x; return y.a; }'
+assert 7 'int main() { struct t {int a; int b;} x, y; x.a = 7; y = x; return y.a; }'
+assert 7 'int main() { struct t {int a; int b;}; struct t x; x.a = 7; struct t y = x; return y.a; }'
+assert 5 'int main() { struct t {char a; long b;} x, y, z; x.b = 5; z = y = x; return z.b; }'
+assert 3 'int main() { union {int a; char b;} x, y; x.a = 3; y = x; return y.b; }'
+assert 8 'int main() { struct {char a; long b;} x; return _Alignof(struct {char a; long b;}) + sizeof(x) - 16; }'
+assert 1 'int main() { return _Alignof(char); }'
+assert 4 'int main() { return _Alignof(int[3]); }'
+assert 2 'struct s {short a; char b;} g = {1, 2}; int main() { return g.b; }'
+assert 9 'struct {int a; struct {char b; int c;} d;} g = {1, {2, 3}}; int main() { return g.a + g.d.b * 4; }'
+assert 4 'union {int a; char b;} g = {260}; int main() { return g.b; }'
+assert 28 'struct s {int a; char *p;} g[2] = {{1, "ab"}, {2}}; int main() { return sizeof(g) - 4 + g[0].p[1] - 98; }'
+
+assert 0 'int x; int main() { return x; }'
+assert 3 'int x; int main() { x = 3; return x; }'
+assert 7 'int x, y; int main() { x = 3; y = 4; return x + y; }'
+assert 5 'int x[4]; int main() { x[0] = 2; x[3] = 3; return x[0] + x[3]; }'
+assert 4 'int x; int main() { return sizeof(x); }'
+assert 16 'int x[4]; int main() { return sizeof(x); }'
+assert 3 'int x = 3; int main() { return x; }'
+assert 2 'int a[3] = {1, 2, 3}; int main() { return a[1]; }'
+assert 0 'int a[3] = {1}; int main() { return a[2]; }'
+assert 104 'char s[6] = "hello"; int main() { return s[0]; }'
+assert 108 'char *p = "hello"; int main() { return p[2]; }'
+assert 3 'int x = 3; int *p = &x; int main() { return *p; }'
+assert 5 'int a[3] = {1, 5, 2}; int *p = a + 1; int main() { return *p; }'
+assert 4 'int a[2][2] = {{1, 2}, {3, 4}}; int main() { return a[1][1]; }'
+assert 9 'int x = 1 + 2 * 4; int main() { return x; }'
+assert 1 'int main() { return g(); } int g() { return x; } int x = 1;'
+assert 5 'int x = 3; int main() { int x = 5; return x; }'
+
+assert 5 'int main() { return 17 % 6; }'
+assert 2 'int main() { return -7 % 3 + 3; }'
+assert 3 'int main() { unsigned x = -1; return x % 7; }'
+assert 0 'int main() { return 0 & 1; }'
+assert 1 'int main() { return 3 & 1; }'
+assert 3 'int main() { return 1 | 2; }'
+assert 6 'int main() { return 5 ^ 3; }'
+assert 3 'int main() { return 1 | 2 & 3 ^ 1; }'
+assert 1 'int main() { return ~-2; }'
+assert 1 'int main() { char c = ~254; return c; }'
+assert 16 'int main() { return 1 << 4; }'
+assert 5 'int main() { return 20 >> 2 - 0 + 0 >> 0; }'
+assert 255 'int main() { return -1 >> 4 & 255; }'
+assert 15 'int main() { unsigned x = -1; return x >> 28; }'
+assert 8 'int main() { char c = 1; return c << 3; }'
+assert 1 'int main() { return !0; }'
+assert 0 'int main() { return !3; }'
+assert 1 'int main() { int *p = 0; return !p; }'
+assert 1 'int main() { return 1 && 2; }'
+assert 0 'int main() { return 1 && 0; }'
+assert 1 'int main() { return 0 || 2; }'
+assert 0 'int main() { return 0 || 0; }'
+assert 3 'int main() { int x = 3; 0 && (x = 5); return x; }'
+assert 3 'int main() { int x = 3; 1 || (x = 5); return x; }'
+assert 5 'int main() { int x = 3; 1 && (x = 5); return x; }'
+assert 1 'int main() { int *p = 0; return p || 1 && 1; }'
+assert 2 'int main() { return 1 ? 2 : 3; }'
+assert 3 'int main() { return 0 ? 2 : 3; }'
+assert 4 'int main() { return 0 ? 2 : 1 ? 4 : 5; }'
+assert 1 'int main() { unsigned u = 2; return (1 ? -1 : u) > 0; }'
+assert 8 'int main() { long l = 2; return sizeof(0 ? 1 : l); }'
+assert 2 'int main() { int a[2]; a[1] = 2; int *p = 0; return (p ? p : a)[1]; }'
+assert 7 'int main() { struct {int a;} x, y; x.a = 3; y.a = 7; return (0 ? x : y).a; }'
+assert 3 'int main() { return (1, 2, 3); }'
+assert 8 'int main() { int i = 2, j = 3; return i = 5, j + i; }'
+assert 7 'int main() { int i = 2; i += 5; return i; }'
+assert 7 'int main() { int i = 2; return i += 5; }'
+assert 3 'int main() { int i = 5; i -= 2; return i; }'
+assert 6 'int main() { int i = 3; i *= 2; return i; }'
+assert 3 'int main() { int i = 6; i /= 2; return i; }'
+assert 2 'int main() { int i = 8; i %= 3; return i; }'
+assert 2 'int main() { int i = 6; i &= 3; return i; }'
+assert 7 'int main() { int i = 6; i |= 3; return i; }'
+assert 5 'int main() { int i = 6; i ^= 3; return i; }'
+assert 24 'int main() { int i = 6; i <<= 2; return i; }'
+assert 1 'int main() { int i = 6; i >>= 2; return i; }'
+assert 0 'int main() { char c = 255; c += 1; return c; }'
+assert 3 'int main() { int a[4]; a[3] = 3; int *p = a; p += 3; return *p; }'
+assert 2 'int main() { int a[3]; a[0]=0; a[1]=1; a[2]=2; int i = 0; a[++i] += 1; return a[1] + i - 1 + a[2] - 2; }'
+assert 3 'int main() { int i = 2; return ++i; }'
+assert 1 'int main() { int i = 2; return --i; }'
+assert 2 'int main() { int i = 2; return i++; }'
+assert 3 'int main() { int i = 2; i++; return i; }'
+assert 2 'int main() { int i = 2; return i--; }'
+assert 1 'int main() { int i = 2; i--; return i; }'
+assert 127 'int main() { char c = 127; return c++; }'
+assert 128 'int main() { char c = 127; c++; return c + 256; }'
+assert 2 'int main() { int a[3]; a[0]=0; a[1]=1; a[2]=2; int *p = a; p++; return *++p; }'
+assert 1 'int main() { int a[3]; a[0]=0; a[1]=1; a[2]=2; int *p = a + 2; p--; return *p--; }'
+assert 10 'int main() { int i; int s = 0; for (i = 0; i < 5; i++) s += i; return s; }'
+assert 6 'int main() { struct {int a;} x; x.a = 5; x.a++; return x.a; }'
+assert 3 $'int main() {\n  return 3;\n}\n'
+assert 2 $'int main() {\r\n\treturn 2;\f\v}'
+assert 5 $'// line comment\nint main() { /* block\n comment */ return 5; // return 6;\n}'
+assert 7 'int main() { return 3 /* comment */ + /**/ 4; }'
+assert 2 'int main() { return 4 / /* not a line comment */ 2; }'
+assert 3 'int main() { return sizeof("/*"); }'
+assert 31 'int main() { return 0x1f; }'
+assert 171 'int main() { return 0XaB; }'
+assert 15 'int main() { return 017; }'
+assert 5 'int main() { return 0b101; }'
+assert 0 'int main() { return 0; }'
+assert 4 'int main() { return sizeof(1u); }'
+assert 8 'int main() { return sizeof(1L) + sizeof(1ll) - sizeof(1UL); }'
+assert 8 'int main() { return sizeof(2147483648); }'
+assert 4 'int main() { return sizeof(0xffffffff); }'
+assert 8 'int main() { return sizeof(0x100000000); }'
+assert 1 'int main() { return 0xffffffff > 0; }'
+assert 1 'int main() { return -1 < 0x7fffffff; }'
+assert 0 'int main() { return -1 < 0xffffffff; }'
+assert 1 'int main() { return -1 < 4294967295; }'
+assert 0 'int main() { return -1 < 1u; }'
+assert 255 'int main() { return 0xffffffffffffffff >> 56; }'
+assert 1 'int main() { return 9223372036854775807 > 0; }'
+assert 64 'int main() { long x = 0x4000000000000000; return x >> 56; }'
+assert 10 'int x = 2 << 2 | 1 ? 10 : 0; int main() { return x; }'
+assert 1 'int x = !0 && ~0; int main() { return x; }'
+
+assert 6 $'#define N 3\nint main() { return N + N; }'
+assert 9 $'#define sq(x) ((x) * (x))\nint main() { return sq(1 + 2); }'
+assert 3 $'#define f(...) add(__VA_ARGS__)\nint main() { return f(1, 2); }'
+assert 6 $'#define s(x) #x\nint main() { return sizeof(s(a + b)); }'
+assert 12 $'#define cat(x, y) x ## y\nint main() { int ab = 12; return cat(a, b); }'
+assert 13 $'#define AB a ## b\nint main() { int ab = 13; return AB; }'
+assert 2 $'int main() {\n  return __LINE__;\n}'
+assert 2 $'#if 0\nint main() { return 1; }\n#elif defined(X) || 1 + 1 == 2\nint main() { return 2; }\n#else\nint main() { return 3; }\n#endif'
+assert 4 $'#define X\n#ifdef X\n# undef X\n#endif\n#ifndef X\nint main() { return 4; }\n#endif'
+mkdir -p tmpinc && printf 'int inc() { return 7; }\n' > tmpinc/tmp.h
+assert 7 $'#include "tmpinc/tmp.h"\nint main() { return inc(); }'
+
+assert_error 'int main() { 1 < }'
+assert_error 'int main() { &1; }'
+assert_error 'int main() { { 1; }'
+assert_error 'int main() { if 1; 2; }'
+assert_error 'int main() { return; }'
+assert_error 'int f(int a); int main() { f(1, 2); }'
+assert_error 'int printf(char *fmt, ...); int main() { printf(); }'
+assert_error 'int f(int a); char f(int a);'
+assert_error 'int main() { return sizeof(int[4611686018427387904]); }'
+assert_error 'int main() { char a[2000000000]; char b[2000000000]; return 0; }'
+assert_error 'int main() { struct {char a[2000000000]; char b[2000000000];} s; return 0; }'
+assert_error 'int f(int n) { va_list ap; va_start(ap, n); return 0; }'
+assert_error 'int f(int n, ...) { int ap; return va_arg(ap, int); }'
+assert_error '1;'
+assert_error 'main() { return 0; }'
+assert_error 'int main() { 1 + $; }'
+assert_error 'int main() { (1 + 2; }'
+assert_error 'int main() { x = 1; }'
+assert_error 'int main() { { int x; } return x; }'
+assert_error 'int main() { int x; int x; }'
+assert_error 'int main() { int x; return *x; }'
+assert_error 'int main() { int *p; return p + p; }'
+assert_error 'int main() { int a[2]; int b[2]; a = b; }'
+assert_error 'int main() { int a[n]; }'
+assert_error 'int main() { return sizeof(y); }'
+assert_error 'int main() { return "abc; }'
+assert_error 'int main() { return "\x"; }'
+assert_error "int main() { return 'ab'; }"
+assert_error "int main() { return ''; }"
+assert_error 'int x = y;'
+assert_error 'int main() { struct {int a;} x; return x.b; }'
+assert_error 'int main() { int x; return x.a; }'
+assert_error 'int main() { struct s x; }'
+assert_error 'int main() { struct s {int a;}; struct s {int b;}; }'
+assert_error 'int main() { struct {int a;} x; int y = x; }'
+assert_error 'int main() { short long x; }'
+assert_error 'int main() { unsigned signed x; }'
+assert_error 'int main() { int *p; return -p; }'
+assert_error 'int main() { int *p; return p * 2; }'
+assert_error 'int y; int x = y;'
+assert_error 'int a[2] = {1, 2, 3};'
+assert_error 'char s[2] = "abc";'
+assert_error 'int x; int x;'
+assert_error 'int f; int f() { return 0; }'
+assert_error 'int main() { int *p; return p % 2; }'
+assert_error 'int main() { int *p; return ~p; }'
+assert_error 'int main() { int *p; return p << 1; }'
+assert_error 'int main() { struct {int a;} x; return !x; }'
+assert_error 'int main() { struct {int a;} x; return x || 1; }'
+assert_error 'int main() { struct {int a;} x; return 1 ? x : 2; }'
+assert_error 'int main() { 1++; }'
+assert_error 'int main() { int a[2]; a += 1; }'
+assert_error 'int main() { int x; (x, x) = 1; }'
+assert_error 'int x = 1 % 0;'
+assert_error $'int main() {\n  return 1; /* unterminated\n}'
+assert_error 'int main() { return 09; }'
+assert_error 'int main() { return 0x; }'
+assert_error 'int main() { return 1lu2; }'
+assert_error 'int main() { return 18446744073709551616; }'
+assert_error 'int main() { return 9223372036854775808; }'
+assert_error $'#error no main\nint main() { return 0; }'
+assert_error $'#if 1\nint main() { return 0; }'
+assert_error $'#define f(x) x\nint main() { return f(1, 2); }'
+assert_error '#include "none.h"'
+
+# input from stdin and from a file
+printf 'int main() { return 1+2; }' | "$compiler" > tmp.s && cc -z noexecstack -o tmp tmp.s tmp2.o && ./tmp
+[ "$?" = 3 ] || { echo "stdin input failed"; exit 1; }
+printf 'int main() { return 3*4; }' > tmp.c && "$compiler" tmp.c > tmp.s && cc -z noexecstack -o tmp tmp.s tmp2.o && ./tmp
+[ "$?" = 12 ] || { echo "file input failed"; exit 1; }
+printf '#include <tmp.h>\nint main() { return inc(); }' > tmp.c && "$compiler" -I tmpinc tmp.c > tmp.s && cc -z noexecstack -o tmp tmp.s tmp2.o && ./tmp
+[ "$?" = 7 ] || { echo "include path failed"; exit 1; }
+
+# calls of a variadic function at any stack depth, with arguments on the stack
+printf 'int printf(char *fmt, ...);\nint main() { int x = 1 + (2 + printf("%%d %%s %%c %%d %%d %%d %%d %%ld\\n", 1, "two", 51, 4, 5, 6, 7, 8l)); return x; }' > tmp.c
+"$compiler" tmp.c > tmp.s && cc -z noexecstack -o tmp tmp.s tmp2.o && [ "$(./tmp)" = "1 two 3 4 5 6 7 8" ] || { echo "printf failed"; exit 1; }
+
+# a variadic function passing its arguments to vprintf
+printf 'int vprintf(char *fmt, va_list ap);\nint say(char *fmt, ...) { va_list ap; va_start(ap, fmt); int n = vprintf(fmt, ap); va_end(ap); return n; }\nint main() { say("%%d %%s %%d %%d %%d %%d %%ld\\n", 1, "two", 3, 4, 5, 6, 7l); return 0; }' > tmp.c
+"$compiler" tmp.c > tmp.s && cc -z noexecstack -o tmp tmp.s tmp2.o && [ "$(./tmp)" = "1 two 3 4 5 6 7" ] || { echo "vprintf failed"; exit 1; }
+
+# `-E` output is C source which both compilers accept
+printf '#include <tmp.h>\n#define twice(x) (x) * 2\nint main() {\n  return twice(inc()) - -1;\n}' > tmp.c
+"$compiler" -E -I tmpinc -o tmp.i tmp.c && grep -q '^# 3 "tmp.c"$' tmp.i || { echo "-E failed"; exit 1; }
+"$compiler" tmp.i > tmp.s && cc -z noexecstack -o tmp tmp.s tmp2.o && ./tmp
+[ "$?" = 15 ] || { echo "-E output failed"; exit 1; }
+cc -xc -z noexecstack -o tmp tmp.i && ./tmp
+[ "$?" = 15 ] || { echo "-E output failed with cc"; exit 1; }
+
+echo OK